@@ -0,0 +1,182 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+use crate::diff::Report;
+use crate::organize::system_name;
+use crate::state::{dat_version, is_dat_name, parse_dat_version};
+
+/// The DATs a source offers, keyed by `<collection>/<system>`, with their versions.
+pub type Catalog = BTreeMap<String, String>;
+
+/// How much of each DAT in a pack is read to find its header version.
+const HEADER_LENGTH: u64 = 64 * 1024;
+
+fn catalog_key(collection: &Path, filename: &str) -> String {
+    collection
+        .join(system_name(filename))
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Lists the DATs in the downloaded artifact at `path`.
+///
+/// DATs in ZIP archives are listed if they match `filter`, under the directory they are in within the
+/// archive or else under `collection`. Artifacts without any DATs, such as Redump cuesheet packs, are
+/// listed as a whole with the `fallback` version.
+pub fn catalog_artifact<P, F>(path: P, collection: &str, filter: F, fallback: &str) -> Result<Catalog>
+where
+    P: AsRef<Path>,
+    F: Fn(&str) -> bool,
+{
+    let path = path.as_ref();
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut catalog = Catalog::new();
+
+    if filename.to_lowercase().ends_with(".zip") {
+        let mut zip = ZipArchive::new(File::open(path)?)?;
+        for i in 0..zip.len() {
+            let member = zip.by_index(i)?;
+            if !member.is_file() || !is_dat_name(member.name()) || !filter(member.name()) {
+                continue;
+            }
+            let member_path = PathBuf::from(member.name());
+            let member_collection = member_path
+                .parent()
+                .filter(|parent| parent != &Path::new(""))
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| PathBuf::from(collection));
+            let crc = member.crc32();
+            let mut header = Vec::new();
+            member.take(HEADER_LENGTH).read_to_end(&mut header).ok();
+            let version = parse_dat_version(&String::from_utf8_lossy(&header))
+                .unwrap_or_else(|| format!("crc32 {:08x}", crc));
+            let member_name = member_path.file_name().unwrap_or_default().to_string_lossy();
+            catalog.insert(catalog_key(&member_collection, &member_name), version);
+        }
+    } else if is_dat_name(&filename) {
+        let version = dat_version(path)?.unwrap_or_else(|| fallback.to_owned());
+        catalog.insert(catalog_key(Path::new(collection), &filename), version);
+    }
+
+    if catalog.is_empty() {
+        catalog.insert(catalog_key(Path::new(collection), &filename), fallback.to_owned());
+    }
+    Ok(catalog)
+}
+
+/// A DAT whose version changed between two catalogs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionChange {
+    pub dat: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// The DATs that appeared, disappeared or changed version between two catalogs of a source.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CatalogChanges {
+    /// The name of the source.
+    pub source: String,
+    /// DATs the source did not offer before, such as new systems.
+    pub appeared: Vec<String>,
+    /// DATs the source no longer offers, such as discontinued or renamed systems.
+    pub disappeared: Vec<String>,
+    /// DATs with a new version.
+    pub changed: Vec<VersionChange>,
+}
+
+impl CatalogChanges {
+    /// Whether the catalogs list the same versions of the same DATs.
+    pub fn is_empty(&self) -> bool {
+        self.appeared.is_empty() && self.disappeared.is_empty() && self.changed.is_empty()
+    }
+
+    /// A one line count of the changes, such as `1 appeared, 2 changed version`.
+    pub fn summary(&self) -> String {
+        let counts = [
+            (self.appeared.len(), "appeared"),
+            (self.disappeared.len(), "disappeared"),
+            (self.changed.len(), "changed version"),
+        ];
+        let summary = counts
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, what)| format!("{} {}", count, what))
+            .collect::<Vec<_>>();
+        if summary.is_empty() {
+            String::from("no DATs changed")
+        } else {
+            summary.join(", ")
+        }
+    }
+}
+
+/// Compares the catalogs of a source from two runs.
+pub fn diff_catalogs(source: &str, old: &Catalog, new: &Catalog) -> CatalogChanges {
+    let mut changes = CatalogChanges {
+        source: source.to_owned(),
+        ..CatalogChanges::default()
+    };
+    for (dat, version) in old {
+        match new.get(dat) {
+            Some(new_version) if new_version != version => changes.changed.push(VersionChange {
+                dat: dat.clone(),
+                from: version.clone(),
+                to: new_version.clone(),
+            }),
+            Some(_) => {}
+            None => changes.disappeared.push(dat.clone()),
+        }
+    }
+    changes.appeared = new
+        .keys()
+        .filter(|dat| !old.contains_key(*dat))
+        .cloned()
+        .collect();
+    changes
+}
+
+impl Report for CatalogChanges {
+    fn to_text(&self) -> String {
+        let mut out = format!("{}: {}\n", self.source, self.summary());
+        for dat in &self.appeared {
+            let _ = writeln!(out, "+ {}", dat);
+        }
+        for dat in &self.disappeared {
+            let _ = writeln!(out, "- {}", dat);
+        }
+        for change in &self.changed {
+            let _ = writeln!(out, "~ {}: {} -> {}", change.dat, change.from, change.to);
+        }
+        out
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = format!("# {} catalog changes\n\n{}\n\n", self.source, self.summary());
+        let sections: [(&str, Vec<String>); 3] = [
+            ("Appeared", self.appeared.iter().map(|dat| format!("- {}", dat)).collect()),
+            ("Disappeared", self.disappeared.iter().map(|dat| format!("- {}", dat)).collect()),
+            (
+                "Changed version",
+                self.changed
+                    .iter()
+                    .map(|change| format!("- {}: {} → {}", change.dat, change.from, change.to))
+                    .collect(),
+            ),
+        ];
+        for (title, lines) in sections.iter().filter(|(_, lines)| !lines.is_empty()) {
+            let _ = writeln!(out, "## {} ({})\n", title, lines.len());
+            for line in lines {
+                let _ = writeln!(out, "{}", line);
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
@@ -1,58 +1,410 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bytes::Bytes;
-use listinfo::de;
 use listinfo::parse;
+use listinfo::{EntryData, EntryFragment, SubEntry};
 use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
-use quick_xml::Writer;
-use serde::{self, Deserialize, Serialize};
-use std::io::Cursor;
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename = "rom", rename_all(serialize = "lowercase"))]
-struct Rom<'a> {
-    name: &'a str,
-    size: &'a str,
-    crc: &'a str,
-    md5: &'a str,
-    sha1: &'a str,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename = "datafile", rename_all(serialize = "lowercase"))]
-struct Datafile<'a> {
-    #[serde(borrow, rename(serialize = "$value"))]
-    clrmamepro: Header<'a>,
-    #[serde(borrow, rename(serialize = "$value"))]
-    game: Vec<Game<'a>>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename = "header", rename_all(serialize = "lowercase"))]
-struct Header<'a> {
-    name: &'a str,
-    description: &'a str,
-    category: &'a str,
-    version: &'a str,
-    author: &'a str,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename = "game", rename_all(serialize = "lowercase"))]
-struct Game<'a> {
-    name: &'a str,
-    description: &'a str,
-    #[serde(rename(serialize = "$value"))]
-    rom: Vec<Rom<'a>>,
-}
-
-
-pub fn convert_to_xml_dat<'a, 'b>(
-    s: &'a str,
-    homepage: &'b str,
+use quick_xml::{Reader, Writer};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{Cursor, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+use crate::dat::{BiosSet, Datafile, Dialect, Field, Game, Header, Release, Rom};
+use crate::state::is_dat_name;
+
+/// The ROM statuses Logiqx has a `status` attribute for. ClrMamePro calls them flags.
+const STATUSES: [&str; 4] = ["baddump", "nodump", "good", "verified"];
+
+/// The game attributes Logiqx and MAME define besides the ones with a place in the model.
+/// ClrMamePro lists them like any other value of a game, so they are told apart by name.
+const GAME_ATTRIBUTES: [&str; 6] = [
+    "sourcefile",
+    "board",
+    "rebuildto",
+    "isdevice",
+    "ismechanical",
+    "runnable",
+];
+
+/// The values of a sub-entry such as `rom ( name "a.bin" size 1 )`, in order.
+fn sub_entry_values(entry: &SubEntry) -> Vec<(String, String)> {
+    entry
+        .iter()
+        .flat_map(|(key, node)| node.iter().map(move |value| (key.to_string(), value.to_string())))
+        .collect()
+}
+
+fn field(data: &EntryData) -> Field {
+    match data {
+        EntryData::Scalar(value) => Field::Value(value.to_string()),
+        EntryData::SubEntry(entry) => Field::Entry(sub_entry_values(entry)),
+    }
+}
+
+fn scalar(data: &EntryData) -> Option<String> {
+    match data {
+        EntryData::Scalar(value) => Some(value.to_string()),
+        EntryData::SubEntry(entry) => entry.value_unique("name").map(str::to_owned),
+    }
+}
+
+fn parse_header(fragment: &EntryFragment) -> Header {
+    let mut header = Header::default();
+    for (key, node) in fragment.iter() {
+        for data in node.iter() {
+            let value = || scalar(data);
+            match key {
+                "name" => header.name = value(),
+                "description" => header.description = value(),
+                "category" => header.category = value(),
+                "version" => header.version = value(),
+                "date" => header.date = value(),
+                "author" => header.author = value(),
+                "email" => header.email = value(),
+                "homepage" => header.homepage = value(),
+                "url" => header.url = value(),
+                "comment" => header.comment = value(),
+                key if key.starts_with("force") || key == "header" => {
+                    header.force.push((key.to_owned(), value().unwrap_or_default()))
+                }
+                key => header.extra.push((key.to_owned(), field(data))),
+            }
+        }
+    }
+    header
+}
+
+/// The value of an optional ROM field, or `None` for the placeholders some DATs use for missing values.
+fn present(value: String) -> Option<String> {
+    Some(value).filter(|value| !value.is_empty() && value != "-")
+}
+
+fn rom_from(values: Vec<(String, String)>) -> Rom {
+    let mut rom = Rom::default();
+    for (key, value) in values {
+        match key.as_str() {
+            "name" => rom.name = value,
+            "size" => rom.size = present(value),
+            "crc" => rom.crc = present(value),
+            "md5" => rom.md5 = present(value),
+            "sha1" => rom.sha1 = present(value),
+            "sha256" => rom.sha256 = present(value),
+            "xxh3_64" => rom.xxh3_64 = present(value),
+            "xxh3_128" => rom.xxh3_128 = present(value),
+            "merge" => rom.merge = Some(value),
+            "status" => rom.status = Some(value),
+            "flags" if STATUSES.contains(&value.as_str()) && rom.status.is_none() => {
+                rom.status = Some(value)
+            }
+            "date" => rom.date = Some(value),
+            "serial" => rom.serial = Some(value),
+            "region" => rom.region = Some(value),
+            _ => rom.extra.push((key, value)),
+        }
+    }
+    rom
+}
+
+fn release_from(values: Vec<(String, String)>) -> Release {
+    let mut release = Release::default();
+    for (key, value) in values {
+        match key.as_str() {
+            "name" => release.name = value,
+            "region" => release.region = Some(value),
+            "language" => release.language = Some(value),
+            "date" => release.date = Some(value),
+            "default" => release.default = Some(value),
+            _ => {}
+        }
+    }
+    release
+}
+
+fn biosset_from(values: Vec<(String, String)>) -> BiosSet {
+    let mut biosset = BiosSet::default();
+    for (key, value) in values {
+        match key.as_str() {
+            "name" => biosset.name = value,
+            "description" => biosset.description = Some(value),
+            "default" => biosset.default = Some(value),
+            _ => {}
+        }
+    }
+    biosset
+}
+
+fn parse_rom(data: &EntryData) -> Result<Rom> {
+    match data {
+        EntryData::SubEntry(entry) => Ok(rom_from(sub_entry_values(entry))),
+        EntryData::Scalar(name) => Err(anyhow!("ROM {} has no fields", name)),
+    }
+}
+
+fn parse_game(fragment: &EntryFragment, is_bios: bool) -> Result<Game> {
+    let mut game = Game {
+        is_bios,
+        ..Game::default()
+    };
+    for (key, node) in fragment.iter() {
+        for data in node.iter() {
+            let value = || scalar(data);
+            match key {
+                "name" => game.name = value().unwrap_or_default(),
+                "description" => game.description = value(),
+                "year" => game.year = value(),
+                "manufacturer" => game.manufacturer = value(),
+                "category" => game.category = value(),
+                "cloneof" => game.cloneof = value(),
+                "romof" => game.romof = value(),
+                "sampleof" => game.sampleof = value(),
+                "serial" => game.serial = value(),
+                "version" => game.version = value(),
+                "region" => game.region = value(),
+                "comment" => game.comments.extend(value()),
+                "sample" => game.samples.extend(value()),
+                "archive" => game.archives.extend(value()),
+                "rom" => game.roms.push(parse_rom(data)?),
+                "disk" => game.disks.push(parse_rom(data)?),
+                "release" => game.releases.push(release_from(sub_entry_values_of(data))),
+                "biosset" => game.biossets.push(biosset_from(sub_entry_values_of(data))),
+                key if GAME_ATTRIBUTES.contains(&key) => {
+                    game.attributes.extend(value().map(|value| (key.to_owned(), value)))
+                }
+                key => game.extra.push((key.to_owned(), field(data))),
+            }
+        }
+    }
+    if game.name.is_empty() {
+        return Err(anyhow!("DAT has a game without a name"));
+    }
+    Ok(game)
+}
+
+fn sub_entry_values_of(data: &EntryData) -> Vec<(String, String)> {
+    match data {
+        EntryData::SubEntry(entry) => sub_entry_values(entry),
+        EntryData::Scalar(_) => Vec::new(),
+    }
+}
+
+/// Reads a ClrMamePro DAT, keeping every field of its header, games and ROMs.
+pub fn parse_cmp_dat(s: &str) -> Result<Datafile> {
+    let document = parse::parse_document(s)?;
+    let mut datafile = Datafile::default();
+    for (key, fragments) in document.iter() {
+        for fragment in fragments {
+            match key {
+                "clrmamepro" => datafile.header = parse_header(fragment),
+                "game" | "machine" => datafile.games.push(parse_game(fragment, false)?),
+                "resource" => datafile.games.push(parse_game(fragment, true)?),
+                _ => {}
+            }
+        }
+    }
+    Ok(datafile)
+}
+
+/// The attributes of an XML element, in order.
+fn xml_attributes(element: &BytesStart, reader: &Reader<&[u8]>) -> Result<Vec<(String, String)>> {
+    element
+        .attributes()
+        .map(|attribute| {
+            let attribute = attribute?;
+            let key = String::from_utf8_lossy(attribute.key).into_owned();
+            Ok((key, attribute.unescape_and_decode_value(reader)?))
+        })
+        .collect()
+}
+
+/// Reads the text of the XML element that was just opened, skipping any elements nested in it.
+fn xml_text(empty: bool, reader: &mut Reader<&[u8]>, buf: &mut Vec<u8>) -> Result<String> {
+    let mut text = String::new();
+    if empty {
+        return Ok(text);
+    }
+    let mut depth = 0;
+    loop {
+        match reader.read_event(buf)? {
+            Event::Text(ref e) if depth == 0 => text.push_str(&e.unescape_and_decode(reader)?),
+            Event::CData(ref e) if depth == 0 => text.push_str(&String::from_utf8_lossy(e)),
+            Event::Start(_) => depth += 1,
+            Event::End(_) if depth == 0 => break,
+            Event::End(_) => depth -= 1,
+            Event::Eof => return Err(anyhow!("DAT ends within an element")),
+            _ => {}
+        }
+        buf.clear();
+    }
+    buf.clear();
+    Ok(text)
+}
+
+fn value_of(key: &str, values: Vec<(String, String)>) -> Option<String> {
+    values.into_iter().find(|(k, _)| k == key).map(|(_, value)| value)
+}
+
+/// Reads an element of the header or a game that has no dedicated place in the model.
+fn xml_field(
+    attributes: Vec<(String, String)>,
+    empty: bool,
+    reader: &mut Reader<&[u8]>,
+    buf: &mut Vec<u8>,
+) -> Result<Field> {
+    let text = xml_text(empty, reader, buf)?;
+    if attributes.is_empty() {
+        Ok(Field::Value(text))
+    } else if text.is_empty() {
+        Ok(Field::Entry(attributes))
+    } else {
+        Ok(Field::Element(attributes, text))
+    }
+}
+
+fn xml_game(attributes: Vec<(String, String)>) -> Result<Game> {
+    let mut game = Game::default();
+    for (key, value) in attributes {
+        match key.as_str() {
+            "name" => game.name = value,
+            "isbios" => game.is_bios = value == "yes",
+            "cloneof" => game.cloneof = Some(value),
+            "romof" => game.romof = Some(value),
+            "sampleof" => game.sampleof = Some(value),
+            _ => game.attributes.push((key, value)),
+        }
+    }
+    if game.name.is_empty() {
+        return Err(anyhow!("DAT has a game without a name"));
+    }
+    Ok(game)
+}
+
+/// Reads a Logiqx XML DAT, keeping every field of its header, games and ROMs.
+pub fn parse_xml_dat(s: &str) -> Result<Datafile> {
+    let mut reader = Reader::from_str(s);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut datafile = Datafile::default();
+    let mut in_header = false;
+    let mut game: Option<Game> = None;
+
+    loop {
+        let (name, attributes, empty) = match reader.read_event(&mut buf)? {
+            Event::Start(ref e) => (e.name().to_vec(), xml_attributes(e, &reader)?, false),
+            Event::Empty(ref e) => (e.name().to_vec(), xml_attributes(e, &reader)?, true),
+            Event::End(ref e) => {
+                match e.name() {
+                    b"header" => in_header = false,
+                    b"game" | b"machine" => datafile.games.extend(game.take()),
+                    _ => {}
+                }
+                buf.clear();
+                continue;
+            }
+            Event::Eof => break,
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+        buf.clear();
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let (reader, buf) = (&mut reader, &mut buf);
+
+        if name == "header" {
+            in_header = !empty;
+        } else if name == "game" || name == "machine" {
+            let parsed = xml_game(attributes)?;
+            if empty {
+                datafile.games.push(parsed);
+            } else {
+                game = Some(parsed);
+            }
+        } else if let Some(game) = game.as_mut() {
+            match name.as_str() {
+                "description" => game.description = Some(xml_text(empty, reader, buf)?),
+                "year" => game.year = Some(xml_text(empty, reader, buf)?),
+                "manufacturer" => game.manufacturer = Some(xml_text(empty, reader, buf)?),
+                "category" => game.category = Some(xml_text(empty, reader, buf)?),
+                "serial" => game.serial = Some(xml_text(empty, reader, buf)?),
+                "version" => game.version = Some(xml_text(empty, reader, buf)?),
+                "region" => game.region = Some(xml_text(empty, reader, buf)?),
+                "comment" => game.comments.push(xml_text(empty, reader, buf)?),
+                "release" | "biosset" | "rom" | "disk" | "sample" | "archive" => {
+                    xml_text(empty, reader, buf)?;
+                    match name.as_str() {
+                        "release" => game.releases.push(release_from(attributes)),
+                        "biosset" => game.biossets.push(biosset_from(attributes)),
+                        "rom" => game.roms.push(rom_from(attributes)),
+                        "disk" => game.disks.push(rom_from(attributes)),
+                        "sample" => game.samples.extend(value_of("name", attributes)),
+                        _ => game.archives.extend(value_of("name", attributes)),
+                    }
+                }
+                _ => {
+                    let field = xml_field(attributes, empty, reader, buf)?;
+                    game.extra.push((name, field));
+                }
+            }
+        } else if in_header {
+            let header = &mut datafile.header;
+            match name.as_str() {
+                "name" => header.name = Some(xml_text(empty, reader, buf)?),
+                "description" => header.description = Some(xml_text(empty, reader, buf)?),
+                "category" => header.category = Some(xml_text(empty, reader, buf)?),
+                "version" => header.version = Some(xml_text(empty, reader, buf)?),
+                "date" => header.date = Some(xml_text(empty, reader, buf)?),
+                "author" => header.author = Some(xml_text(empty, reader, buf)?),
+                "email" => header.email = Some(xml_text(empty, reader, buf)?),
+                "homepage" => header.homepage = Some(xml_text(empty, reader, buf)?),
+                "url" => header.url = Some(xml_text(empty, reader, buf)?),
+                "comment" => header.comment = Some(xml_text(empty, reader, buf)?),
+                "clrmamepro" => {
+                    xml_text(empty, reader, buf)?;
+                    header.force.extend(attributes);
+                }
+                _ => {
+                    let field = xml_field(attributes, empty, reader, buf)?;
+                    header.extra.push((name, field));
+                }
+            }
+        }
+    }
+    Ok(datafile)
+}
+
+/// Converts a ClrMamePro DAT to Logiqx XML, naming every game after its description, or after what
+/// `normalizer` derives from it.
+pub fn convert_to_xml_dat(
+    s: &str,
+    homepage: &str,
+    normalizer: Option<fn(&str) -> String>,
+) -> Result<Bytes> {
+    let mut doc = parse_cmp_dat(s)?;
+    for game in doc.games.iter_mut() {
+        // Games without a category of their own fall under that of the header
+        if let Some(category) = &doc.header.category {
+            game.category.get_or_insert_with(|| category.clone());
+        }
+        if normalizer.is_none() {
+            if let Some(description) = &game.description {
+                game.name = description.clone();
+            }
+        }
+    }
+    write_xml_dat(&doc, Some(homepage), normalizer)
+}
+
+/// Writes a DAT as Logiqx XML.
+///
+/// `homepage` is used if the DAT does not name its own. If given, `normalizer` derives the name of
+/// every game from its description.
+pub fn write_xml_dat(
+    doc: &Datafile,
+    homepage: Option<&str>,
     normalizer: Option<fn(&str) -> String>,
 ) -> Result<Bytes> {
-    let parsed_cmp = parse::parse_document(s)?;
-    let doc: Datafile = de::from_document(&parsed_cmp)?;
     let mut writer = Writer::new_with_indent(Cursor::new(Vec::new()), b'\t', 1);
     // write decl
     writer.write_event(Event::Decl(BytesDecl::new(b"1.0", None, None)))?;
@@ -61,17 +413,17 @@ pub fn convert_to_xml_dat<'a, 'b>(
         r#" datafile PUBLIC "-//Logiqx//DTD ROM Management Datafile//EN" "http://www.logiqx.com/Dats/datafile.dtd""#)))?;
     let datafile = BytesStart::borrowed_name(b"datafile");
     writer.write_event(Event::Start(datafile))?;
-    push_header(&doc, homepage, &mut writer)?;
-    for game in doc.game.iter() {
-        push_game(game, doc.clrmamepro.category, &mut writer, normalizer)?;
+    push_header(&doc.header, homepage, &mut writer)?;
+    for game in doc.games.iter() {
+        push_game(game, &mut writer, normalizer)?;
     }
     writer.write_event(Event::End(BytesEnd::borrowed(b"datafile")))?;
     Ok(Bytes::from(writer.into_inner().into_inner()))
 }
 
-fn push_elem_text<'a>(
+fn push_elem_text(
     name: &[u8],
-    text: &'a str,
+    text: &str,
     writer: &mut Writer<Cursor<Vec<u8>>>,
 ) -> Result<()> {
     writer.write_event(Event::Start(BytesStart::borrowed_name(name)))?;
@@ -80,20 +432,77 @@ fn push_elem_text<'a>(
     Ok(())
 }
 
-fn push_header(doc: &Datafile, homepage: &str, writer: &mut Writer<Cursor<Vec<u8>>>) -> Result<()> {
+fn push_opt_text(
+    name: &[u8],
+    text: &Option<String>,
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+) -> Result<()> {
+    match text {
+        Some(text) => push_elem_text(name, text, writer),
+        None => Ok(()),
+    }
+}
+
+fn push_empty(
+    name: &str,
+    attributes: &[(&str, &Option<String>)],
+    extra: &[(String, String)],
+    writer: &mut Writer<Cursor<Vec<u8>>>,
+) -> Result<()> {
+    let mut elem = BytesStart::owned_name(name.as_bytes());
+    for (key, value) in attributes {
+        if let Some(value) = value {
+            elem.push_attribute((*key, value.as_str()));
+        }
+    }
+    for (key, value) in extra {
+        elem.push_attribute((key.as_str(), value.as_str()));
+    }
+    writer.write_event(Event::Empty(elem))?;
+    Ok(())
+}
+
+fn push_extra(extra: &[(String, Field)], writer: &mut Writer<Cursor<Vec<u8>>>) -> Result<()> {
+    for (key, field) in extra {
+        match field {
+            Field::Value(value) => push_elem_text(key.as_bytes(), value, writer)?,
+            Field::Entry(values) => push_empty(key, &[], values, writer)?,
+            Field::Element(values, text) => {
+                let mut elem = BytesStart::owned_name(key.as_bytes());
+                for (key, value) in values {
+                    elem.push_attribute((key.as_str(), value.as_str()));
+                }
+                writer.write_event(Event::Start(elem))?;
+                writer.write_event(Event::Text(BytesText::from_plain_str(text)))?;
+                writer.write_event(Event::End(BytesEnd::borrowed(key.as_bytes())))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn push_header(header: &Header, homepage: Option<&str>, writer: &mut Writer<Cursor<Vec<u8>>>) -> Result<()> {
     writer.write_event(Event::Start(BytesStart::borrowed_name(b"header")))?;
-    push_elem_text(b"name", doc.clrmamepro.name, writer)?;
-    push_elem_text(b"description", doc.clrmamepro.description, writer)?;
-    push_elem_text(b"version", doc.clrmamepro.version, writer)?;
-    push_elem_text(b"author", doc.clrmamepro.author, writer)?;
-    push_elem_text(b"homepage", homepage, writer)?;
+    push_opt_text(b"name", &header.name, writer)?;
+    push_opt_text(b"description", &header.description, writer)?;
+    push_opt_text(b"category", &header.category, writer)?;
+    push_opt_text(b"version", &header.version, writer)?;
+    push_opt_text(b"date", &header.date, writer)?;
+    push_opt_text(b"author", &header.author, writer)?;
+    push_opt_text(b"email", &header.email, writer)?;
+    push_opt_text(b"homepage", &header.homepage.clone().or_else(|| homepage.map(str::to_owned)), writer)?;
+    push_opt_text(b"url", &header.url, writer)?;
+    push_opt_text(b"comment", &header.comment, writer)?;
+    push_extra(&header.extra, writer)?;
+    if !header.force.is_empty() {
+        push_empty("clrmamepro", &[], &header.force, writer)?;
+    }
     writer.write_event(Event::End(BytesEnd::borrowed(b"header")))?;
     Ok(())
 }
 
 fn push_game<T>(
     game: &Game,
-    category: &str,
     writer: &mut Writer<Cursor<Vec<u8>>>,
     normalizer: Option<T>,
 ) -> Result<()>
@@ -101,29 +510,524 @@ where
     T: Fn(&str) -> String,
 {
     let mut elem = BytesStart::borrowed_name(b"game");
-    if let Some(f) = normalizer {
-        let normalized = f(game.description);
-        elem.push_attribute(("name", normalized.as_str()));
-    } else {
-        elem.push_attribute(("name", game.description));
+    match normalizer {
+        Some(f) => {
+            let normalized = f(game.description.as_deref().unwrap_or(&game.name));
+            elem.push_attribute(("name", normalized.as_str()));
+        }
+        None => elem.push_attribute(("name", game.name.as_str())),
+    }
+    if game.is_bios {
+        elem.push_attribute(("isbios", "yes"));
+    }
+    for (key, value) in &[
+        ("cloneof", &game.cloneof),
+        ("romof", &game.romof),
+        ("sampleof", &game.sampleof),
+    ] {
+        if let Some(value) = value {
+            elem.push_attribute((*key, value.as_str()));
+        }
+    }
+    for (key, value) in game.attributes.iter() {
+        elem.push_attribute((key.as_str(), value.as_str()));
     }
     writer.write_event(Event::Start(elem))?;
-    push_elem_text(b"category", category, writer)?;
-    push_elem_text(b"description", game.description, writer)?;
-    for rom in game.rom.iter() {
-        push_rom(rom, writer)?;
+    for comment in game.comments.iter() {
+        push_elem_text(b"comment", comment, writer)?;
+    }
+    push_opt_text(b"category", &game.category, writer)?;
+    push_elem_text(b"description", game.description.as_deref().unwrap_or(&game.name), writer)?;
+    push_opt_text(b"year", &game.year, writer)?;
+    push_opt_text(b"manufacturer", &game.manufacturer, writer)?;
+    push_opt_text(b"serial", &game.serial, writer)?;
+    push_opt_text(b"version", &game.version, writer)?;
+    push_opt_text(b"region", &game.region, writer)?;
+    for release in game.releases.iter() {
+        push_empty(
+            "release",
+            &[
+                ("name", &Some(release.name.clone())),
+                ("region", &release.region),
+                ("language", &release.language),
+                ("date", &release.date),
+                ("default", &release.default),
+            ],
+            &[],
+            writer,
+        )?;
+    }
+    for biosset in game.biossets.iter() {
+        push_empty(
+            "biosset",
+            &[
+                ("name", &Some(biosset.name.clone())),
+                ("description", &biosset.description),
+                ("default", &biosset.default),
+            ],
+            &[],
+            writer,
+        )?;
+    }
+    for rom in game.roms.iter() {
+        push_rom("rom", rom, writer)?;
     }
+    for disk in game.disks.iter() {
+        push_rom("disk", disk, writer)?;
+    }
+    for sample in game.samples.iter() {
+        push_empty("sample", &[("name", &Some(sample.clone()))], &[], writer)?;
+    }
+    for archive in game.archives.iter() {
+        push_empty("archive", &[("name", &Some(archive.clone()))], &[], writer)?;
+    }
+    push_extra(&game.extra, writer)?;
     writer.write_event(Event::End(BytesEnd::borrowed(b"game")))?;
     Ok(())
 }
 
-fn push_rom(rom: &Rom, writer: &mut Writer<Cursor<Vec<u8>>>) -> Result<()> {
-    let mut elem = BytesStart::borrowed_name(b"rom");
-    elem.push_attribute(("name", rom.name));
-    elem.push_attribute(("size", rom.size));
-    elem.push_attribute(("crc", rom.crc));
-    elem.push_attribute(("md5", rom.md5));
-    elem.push_attribute(("sha1", rom.sha1));
-    writer.write_event(Event::Empty(elem))?;
+fn push_rom(name: &str, rom: &Rom, writer: &mut Writer<Cursor<Vec<u8>>>) -> Result<()> {
+    push_empty(
+        name,
+        &[
+            ("name", &Some(rom.name.clone())),
+            ("size", &rom.size),
+            ("crc", &rom.crc),
+            ("md5", &rom.md5),
+            ("sha1", &rom.sha1),
+            ("sha256", &rom.sha256),
+            ("xxh3_64", &rom.xxh3_64),
+            ("xxh3_128", &rom.xxh3_128),
+            ("merge", &rom.merge),
+            ("status", &rom.status),
+            ("date", &rom.date),
+            ("serial", &rom.serial),
+            ("region", &rom.region),
+        ],
+        &rom.extra,
+        writer,
+    )
+}
+
+/// Quotes a ClrMamePro value. ClrMamePro has no escapes, so values with double quotes cannot be written.
+fn cmp_quote(value: &str) -> Result<String> {
+    if value.contains('"') {
+        return Err(anyhow!("ClrMamePro DATs cannot hold the double quotes in {:?}", value));
+    }
+    Ok(format!("\"{}\"", value))
+}
+
+/// Writes a ClrMamePro entry such as `rom ( name "a.bin" size 1 )` on one line.
+fn cmp_entry(key: &str, values: &[(&str, String)]) -> String {
+    let mut entry = format!("{} (", key);
+    for (key, value) in values {
+        let _ = write!(entry, " {} {}", key, value);
+    }
+    entry + " )"
+}
+
+fn cmp_rom(key: &str, rom: &Rom) -> Result<String> {
+    let mut values = vec![("name", cmp_quote(&rom.name)?)];
+    for (key, value) in &[
+        ("size", &rom.size),
+        ("crc", &rom.crc),
+        ("md5", &rom.md5),
+        ("sha1", &rom.sha1),
+        ("sha256", &rom.sha256),
+        ("xxh3_64", &rom.xxh3_64),
+        ("xxh3_128", &rom.xxh3_128),
+    ] {
+        if let Some(value) = value {
+            values.push((key, value.clone()));
+        }
+    }
+    if let Some(merge) = &rom.merge {
+        values.push(("merge", cmp_quote(merge)?));
+    }
+    if let Some(status) = &rom.status {
+        values.push(("flags", status.clone()));
+    }
+    for (key, value) in &[("date", &rom.date), ("serial", &rom.serial), ("region", &rom.region)] {
+        if let Some(value) = value {
+            values.push((key, cmp_quote(value)?));
+        }
+    }
+    for (key, value) in rom.extra.iter() {
+        values.push((key, cmp_quote(value)?));
+    }
+    Ok(cmp_entry(key, &values))
+}
+
+fn push_cmp_extra(extra: &[(String, Field)], out: &mut String) -> Result<()> {
+    for (key, field) in extra {
+        let _ = match field {
+            Field::Value(value) => writeln!(out, "\t{} {}", key, cmp_quote(value)?),
+            Field::Entry(values) => {
+                let values = values
+                    .iter()
+                    .map(|(key, value)| Ok((key.as_str(), cmp_quote(value)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                writeln!(out, "\t{}", cmp_entry(key, &values))
+            }
+            Field::Element(values, text) => {
+                let mut values = values
+                    .iter()
+                    .map(|(key, value)| Ok((key.as_str(), cmp_quote(value)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                values.push(("text", cmp_quote(text)?));
+                writeln!(out, "\t{}", cmp_entry(key, &values))
+            }
+        };
+    }
+    Ok(())
+}
+
+fn push_cmp_values(values: &[(&str, &Option<String>)], out: &mut String) -> Result<()> {
+    for (key, value) in values {
+        if let Some(value) = value {
+            let _ = writeln!(out, "\t{} {}", key, cmp_quote(value)?);
+        }
+    }
     Ok(())
 }
+
+/// Writes a DAT as a ClrMamePro DAT.
+///
+/// Fails if a value holds double quotes, which ClrMamePro DATs cannot represent.
+pub fn write_cmp_dat(doc: &Datafile) -> Result<Bytes> {
+    let header = &doc.header;
+    let mut out = String::from("clrmamepro (\n");
+    push_cmp_values(
+        &[
+            ("name", &header.name),
+            ("description", &header.description),
+            ("category", &header.category),
+            ("version", &header.version),
+            ("date", &header.date),
+            ("author", &header.author),
+            ("email", &header.email),
+            ("homepage", &header.homepage),
+            ("url", &header.url),
+            ("comment", &header.comment),
+        ],
+        &mut out,
+    )?;
+    for (key, value) in header.force.iter() {
+        let _ = writeln!(out, "\t{} {}", key, cmp_quote(value)?);
+    }
+    push_cmp_extra(&header.extra, &mut out)?;
+    out.push_str(")\n");
+
+    for game in doc.games.iter() {
+        let _ = writeln!(out, "\n{} (", if game.is_bios { "resource" } else { "game" });
+        let _ = writeln!(out, "\tname {}", cmp_quote(&game.name)?);
+        for comment in game.comments.iter() {
+            let _ = writeln!(out, "\tcomment {}", cmp_quote(comment)?);
+        }
+        push_cmp_values(
+            &[
+                ("description", &game.description),
+                ("year", &game.year),
+                ("manufacturer", &game.manufacturer),
+                ("category", &game.category),
+                ("cloneof", &game.cloneof),
+                ("romof", &game.romof),
+                ("sampleof", &game.sampleof),
+                ("serial", &game.serial),
+                ("version", &game.version),
+                ("region", &game.region),
+            ],
+            &mut out,
+        )?;
+        for (key, value) in game.attributes.iter() {
+            let _ = writeln!(out, "\t{} {}", key, cmp_quote(value)?);
+        }
+        for release in game.releases.iter() {
+            let mut values = vec![("name", cmp_quote(&release.name)?)];
+            for (key, value) in &[
+                ("region", &release.region),
+                ("language", &release.language),
+                ("date", &release.date),
+                ("default", &release.default),
+            ] {
+                if let Some(value) = value {
+                    values.push((key, cmp_quote(value)?));
+                }
+            }
+            let _ = writeln!(out, "\t{}", cmp_entry("release", &values));
+        }
+        for biosset in game.biossets.iter() {
+            let mut values = vec![("name", cmp_quote(&biosset.name)?)];
+            for (key, value) in &[("description", &biosset.description), ("default", &biosset.default)] {
+                if let Some(value) = value {
+                    values.push((key, cmp_quote(value)?));
+                }
+            }
+            let _ = writeln!(out, "\t{}", cmp_entry("biosset", &values));
+        }
+        for rom in game.roms.iter() {
+            let _ = writeln!(out, "\t{}", cmp_rom("rom", rom)?);
+        }
+        for disk in game.disks.iter() {
+            let _ = writeln!(out, "\t{}", cmp_rom("disk", disk)?);
+        }
+        for sample in game.samples.iter() {
+            let _ = writeln!(out, "\tsample {}", cmp_quote(sample)?);
+        }
+        for archive in game.archives.iter() {
+            let _ = writeln!(out, "\t{}", cmp_entry("archive", &[("name", cmp_quote(archive)?)]));
+        }
+        push_cmp_extra(&game.extra, &mut out)?;
+        out.push_str(")\n");
+    }
+    Ok(Bytes::from(out))
+}
+
+/// The separator of the fields of a RomCenter game line.
+const RC_SEPARATOR: &str = "¬";
+
+/// The RomCenter format version written to the `[DAT]` section.
+const RC_VERSION: &str = "2.50";
+
+/// The RomCenter plugin written to the `[DAT]` section if the DAT does not name one.
+const RC_PLUGIN: &str = "arcade.dll";
+
+/// Reads a ROM line of the `[GAMES]` or `[RESOURCES]` section of a RomCenter DAT, adding the ROM
+/// to its game. Returns the index of the game.
+///
+/// Lines are `¬parent¬parent description¬game¬description¬rom¬crc¬size¬romof¬merge¬`.
+fn parse_rc_rom(line: &str, games: &mut Vec<Game>, indices: &mut HashMap<String, usize>) -> Result<usize> {
+    let fields = line.split(RC_SEPARATOR).map(str::trim).collect::<Vec<_>>();
+    if fields.len() < 8 || !fields[0].is_empty() {
+        return Err(anyhow!("Malformed RomCenter game line {}", line));
+    }
+    let field = |i: usize| fields.get(i).and_then(|value| present(value.to_string()));
+    let name = field(3).ok_or_else(|| anyhow!("RomCenter game line {} has no game", line))?;
+
+    let index = *indices.entry(name.clone()).or_insert_with(|| {
+        games.push(Game {
+            cloneof: field(1).filter(|parent| parent != &name),
+            description: field(4),
+            romof: field(8),
+            name: name.clone(),
+            ..Game::default()
+        });
+        games.len() - 1
+    });
+    if let Some(rom) = field(5) {
+        games[index].roms.push(Rom {
+            name: rom,
+            crc: field(6),
+            size: field(7),
+            merge: field(9),
+            ..Rom::default()
+        });
+    }
+    Ok(index)
+}
+
+/// Reads a RomCenter 2.x or 3.x DAT.
+///
+/// RomCenter lists nothing but the name, CRC and size of ROMs, so that is all that is read of them.
+pub fn parse_rc_dat(s: &str) -> Result<Datafile> {
+    let mut datafile = Datafile::default();
+    let mut indices = HashMap::new();
+    let mut section = String::new();
+
+    for line in s.trim_start_matches('\u{feff}').lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].to_ascii_uppercase();
+            continue;
+        }
+        if section == "GAMES" {
+            parse_rc_rom(line, &mut datafile.games, &mut indices)?;
+            continue;
+        }
+        // Resources are the BIOS sets games depend on, listed like games.
+        if section == "RESOURCES" {
+            let index = parse_rc_rom(line, &mut datafile.games, &mut indices)?;
+            datafile.games[index].is_bios = true;
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim().to_owned()),
+            None => continue,
+        };
+        let header = &mut datafile.header;
+        match (section.as_str(), key.as_str()) {
+            ("CREDITS", "author") => header.author = present(value),
+            ("CREDITS", "version") => header.version = present(value),
+            ("CREDITS", "email") => header.email = present(value),
+            ("CREDITS", "homepage") => header.homepage = present(value),
+            ("CREDITS", "url") => header.url = present(value),
+            ("CREDITS", "date") => header.date = present(value),
+            ("CREDITS", "comment") => header.comment = present(value),
+            ("DAT", "plugin") => header
+                .extra
+                .push((String::from("romcenter"), Field::Entry(vec![(key, value)]))),
+            ("DAT", "split") if value == "1" => {
+                header.force.push((String::from("forcemerging"), String::from("split")))
+            }
+            ("DAT", "merge") if value == "1" => {
+                header.force.push((String::from("forcemerging"), String::from("merged")))
+            }
+            ("EMULATOR", "refname") => header.name = present(value),
+            ("EMULATOR", "version") => header.emulator_version = present(value),
+            _ => {}
+        }
+    }
+    Ok(datafile)
+}
+
+/// Encodes a RomCenter DAT as Latin-1, failing on characters Latin-1 has no place for.
+fn latin1(s: &str) -> Result<Vec<u8>> {
+    s.chars()
+        .map(|c| match c as u32 {
+            code if code < 0x100 => Ok(code as u8),
+            _ => Err(anyhow!("RomCenter DATs are Latin-1, which cannot hold {:?}", c)),
+        })
+        .collect()
+}
+
+/// Writes a DAT as a RomCenter 2.50 DAT, in Latin-1 with Windows line endings.
+///
+/// RomCenter lists nothing but the name, CRC and size of ROMs, so other hashes, disks and fields
+/// without a place in the format are left out, as are games without ROMs. BIOS sets are listed in
+/// the `[RESOURCES]` section. Fails on text that Latin-1 or the fields of a game line cannot hold.
+pub fn write_rc_dat(doc: &Datafile) -> Result<Bytes> {
+    let header = &doc.header;
+    let plugin = header.extra.iter().find_map(|(key, field)| match field {
+        Field::Entry(values) if key == "romcenter" => values
+            .iter()
+            .find(|(key, _)| key == "plugin")
+            .map(|(_, plugin)| plugin.as_str()),
+        _ => None,
+    });
+    let merging = header
+        .force
+        .iter()
+        .find(|(key, _)| key == "forcemerging")
+        .map(|(_, value)| value.as_str());
+
+    let mut out = String::from("[CREDITS]\r\n");
+    for (key, value) in &[
+        ("author", &header.author),
+        ("version", &header.version),
+        ("email", &header.email),
+        ("homepage", &header.homepage),
+        ("url", &header.url),
+        ("date", &header.date),
+        ("comment", &header.comment),
+    ] {
+        if let Some(value) = value {
+            let _ = write!(out, "{}={}\r\n", key, value);
+        }
+    }
+    let _ = write!(
+        out,
+        "[DAT]\r\nversion={}\r\nplugin={}\r\nsplit={}\r\nmerge={}\r\n",
+        RC_VERSION,
+        plugin.unwrap_or(RC_PLUGIN),
+        (merging == Some("split")) as u8,
+        matches!(merging, Some("merged") | Some("full")) as u8,
+    );
+    let _ = write!(
+        out,
+        "[EMULATOR]\r\nrefname={}\r\nversion={}\r\n",
+        header.name.as_deref().unwrap_or_default(),
+        header
+            .emulator_version
+            .as_deref()
+            .or(header.description.as_deref())
+            .or(header.name.as_deref())
+            .unwrap_or_default(),
+    );
+
+    let descriptions = doc
+        .games
+        .iter()
+        .map(|game| (game.name.as_str(), game.description.as_deref().unwrap_or(&game.name)))
+        .collect::<HashMap<_, _>>();
+    for (section, is_bios) in &[("RESOURCES", true), ("GAMES", false)] {
+        let games = doc.games.iter().filter(|game| game.is_bios == *is_bios).collect::<Vec<_>>();
+        if *is_bios && games.is_empty() {
+            continue;
+        }
+        let _ = write!(out, "[{}]\r\n", section);
+        for game in games {
+            let parent = game.cloneof.as_deref().unwrap_or(&game.name);
+            let parent_description = descriptions.get(parent).copied().unwrap_or(parent);
+            let description = game.description.as_deref().unwrap_or(&game.name);
+            for rom in game.roms.iter() {
+                let fields = [
+                    parent,
+                    parent_description,
+                    &game.name,
+                    description,
+                    &rom.name,
+                    rom.crc.as_deref().unwrap_or_default(),
+                    rom.size.as_deref().unwrap_or_default(),
+                    game.romof.as_deref().unwrap_or_default(),
+                    rom.merge.as_deref().unwrap_or_default(),
+                ];
+                if let Some(field) = fields.iter().find(|field| field.contains(RC_SEPARATOR)) {
+                    return Err(anyhow!("RomCenter DATs cannot hold the separator in {:?}", field));
+                }
+                let _ = write!(out, "{0}{1}{0}\r\n", RC_SEPARATOR, fields.join(RC_SEPARATOR));
+            }
+        }
+    }
+    Ok(Bytes::from(latin1(&out)?))
+}
+
+/// Reads a DAT in any dialect.
+pub fn read_dat(contents: &str) -> Result<Datafile> {
+    match Dialect::detect(contents) {
+        Dialect::Logiqx => parse_xml_dat(contents),
+        Dialect::ClrMamePro => parse_cmp_dat(contents),
+        Dialect::RomCenter => parse_rc_dat(contents),
+    }
+}
+
+/// Reads the DAT at `path`, which may also be a ZIP archive of exactly one DAT.
+pub fn read_dat_file<P: AsRef<Path>>(path: P) -> Result<Datafile> {
+    let path = path.as_ref();
+    let contents = if path.extension().map(|ext| ext.eq_ignore_ascii_case("zip")) == Some(true) {
+        let mut zip = ZipArchive::new(File::open(path)?)?;
+        let dats = zip
+            .file_names()
+            .filter(|name| is_dat_name(name))
+            .map(|name| name.to_owned())
+            .collect::<Vec<_>>();
+        match dats.as_slice() {
+            [name] => {
+                let mut contents = Vec::new();
+                zip.by_name(name)?.read_to_end(&mut contents)?;
+                contents
+            }
+            _ => return Err(anyhow!("{:?} does not contain exactly one DAT", path)),
+        }
+    } else {
+        fs::read(path)?
+    };
+    read_dat(&decode(contents))
+}
+
+/// Decodes the contents of a DAT as UTF-8, or else as Latin-1, which older RomCenter DATs are written in.
+fn decode(contents: Vec<u8>) -> String {
+    String::from_utf8(contents)
+        .unwrap_or_else(|e| e.into_bytes().into_iter().map(char::from).collect())
+}
+
+/// Writes a DAT in the given dialect.
+pub fn write_dat(doc: &Datafile, dialect: Dialect) -> Result<Bytes> {
+    match dialect {
+        Dialect::Logiqx => write_xml_dat(doc, None, None),
+        Dialect::ClrMamePro => write_cmp_dat(doc),
+        Dialect::RomCenter => write_rc_dat(doc),
+    }
+}
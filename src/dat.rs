@@ -0,0 +1,152 @@
+//! An in-memory model of DATs, shared by every DAT dialect atsumare reads and writes.
+
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+
+/// A field of a DAT entry that has no dedicated place in the model, kept so that conversions carry it over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// A single value, such as `board "CPS2"`.
+    Value(String),
+    /// An entry of values, such as `video ( screen raster orientation vertical )`.
+    Entry(Vec<(String, String)>),
+    /// An XML element with both attributes and text, such as `<publisher region="EU">Nintendo</publisher>`.
+    ///
+    /// ClrMamePro entries cannot hold text, so it is written there as the `text` value of the entry.
+    Element(Vec<(String, String)>, String),
+}
+
+/// The header of a DAT.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Header {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub category: Option<String>,
+    pub version: Option<String>,
+    pub date: Option<String>,
+    pub author: Option<String>,
+    pub email: Option<String>,
+    pub homepage: Option<String>,
+    pub url: Option<String>,
+    pub comment: Option<String>,
+    /// The version of the emulator the DAT is for, such as `MAME 0.37b10`.
+    ///
+    /// Only RomCenter DATs have a place for it, in the `[EMULATOR]` section.
+    pub emulator_version: Option<String>,
+    /// How ROM managers should treat the sets, such as `forcemerging full` or `header "nes.xml"`.
+    ///
+    /// Logiqx lists these as attributes of the `clrmamepro` element of the header.
+    pub force: Vec<(String, String)>,
+    pub extra: Vec<(String, Field)>,
+}
+
+/// A release of a game in a region.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Release {
+    pub name: String,
+    pub region: Option<String>,
+    pub language: Option<String>,
+    pub date: Option<String>,
+    pub default: Option<String>,
+}
+
+/// A BIOS a game can run with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BiosSet {
+    pub name: String,
+    pub description: Option<String>,
+    pub default: Option<String>,
+}
+
+/// A ROM or disk image of a game.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rom {
+    pub name: String,
+    pub size: Option<String>,
+    pub crc: Option<String>,
+    pub md5: Option<String>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+    /// The 64-bit XXH3 hash, as listed by some newer DATs.
+    pub xxh3_64: Option<String>,
+    /// The 128-bit XXH3 hash, as listed by some newer DATs.
+    pub xxh3_128: Option<String>,
+    /// The name of the ROM in the parent set, if it is shared with the parent.
+    pub merge: Option<String>,
+    /// How good the dump is: `baddump`, `nodump`, `good` or `verified`.
+    pub status: Option<String>,
+    pub date: Option<String>,
+    pub serial: Option<String>,
+    pub region: Option<String>,
+    pub extra: Vec<(String, String)>,
+}
+
+/// A game, or a BIOS or device set, of a DAT.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Game {
+    pub name: String,
+    /// Whether the set is a BIOS, which ClrMamePro lists as a `resource` rather than a `game`.
+    pub is_bios: bool,
+    pub description: Option<String>,
+    pub year: Option<String>,
+    pub manufacturer: Option<String>,
+    pub category: Option<String>,
+    pub cloneof: Option<String>,
+    pub romof: Option<String>,
+    pub sampleof: Option<String>,
+    /// Attributes of a Logiqx game element with no dedicated place in the model, such as MAME's `isdevice`.
+    pub attributes: Vec<(String, String)>,
+    pub serial: Option<String>,
+    pub version: Option<String>,
+    pub region: Option<String>,
+    pub comments: Vec<String>,
+    pub releases: Vec<Release>,
+    pub biossets: Vec<BiosSet>,
+    pub roms: Vec<Rom>,
+    pub disks: Vec<Rom>,
+    pub samples: Vec<String>,
+    pub archives: Vec<String>,
+    pub extra: Vec<(String, Field)>,
+}
+
+/// The contents of a DAT.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Datafile {
+    pub header: Header,
+    pub games: Vec<Game>,
+}
+
+/// The dialects DATs can be read and written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// Logiqx XML, as shipped by No-Intro and TOSEC.
+    Logiqx,
+    /// The ClrMamePro text format, as shipped by Redump.
+    ClrMamePro,
+    /// The INI-style format of RomCenter 2.x and 3.x.
+    RomCenter,
+}
+
+impl FromStr for Dialect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "logiqx" | "xml" => Ok(Dialect::Logiqx),
+            "clrmamepro" | "cmp" => Ok(Dialect::ClrMamePro),
+            "romcenter" | "rc" => Ok(Dialect::RomCenter),
+            _ => Err(anyhow!("Unknown DAT dialect {}", s)),
+        }
+    }
+}
+
+impl Dialect {
+    /// Guesses the dialect of a DAT from its contents.
+    pub fn detect(contents: &str) -> Dialect {
+        match contents.trim_start_matches('\u{feff}').trim_start().chars().next() {
+            Some('<') => Dialect::Logiqx,
+            Some('[') => Dialect::RomCenter,
+            _ => Dialect::ClrMamePro,
+        }
+    }
+}
@@ -0,0 +1,615 @@
+use anyhow::{anyhow, Error, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use zip::ZipArchive;
+
+use crate::convert;
+use crate::dat;
+use crate::organize::system_name;
+use crate::snapshot::list_files;
+use crate::state::{is_dat_name, relative_path};
+
+/// A ROM or disk of a game, as far as comparing DATs is concerned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rom {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl Rom {
+    /// Whether the ROM has different contents than `other`.
+    ///
+    /// Only the hashes both DATs list are compared.
+    fn differs(&self, other: &Rom) -> bool {
+        differs(&self.size, &other.size)
+            || differs(&self.crc, &other.crc)
+            || differs(&self.md5, &other.md5)
+            || differs(&self.sha1, &other.sha1)
+            || differs(&self.sha256, &other.sha256)
+    }
+
+    /// The strongest identity of the ROM contents the DAT lists.
+    fn fingerprint(&self) -> Option<String> {
+        let hash = self
+            .sha1
+            .as_ref()
+            .or(self.sha256.as_ref())
+            .or(self.md5.as_ref())
+            .or(self.crc.as_ref())?;
+        Some(format!("{}:{}", self.size.unwrap_or_default(), hash))
+    }
+
+    /// Describes how the contents of the ROM changed into those of `new`.
+    ///
+    /// Like [`Rom::differs`], only describes the hashes both DATs list.
+    fn describe_change(&self, new: &Rom) -> String {
+        let mut changes = Vec::new();
+        if differs(&self.size, &new.size) {
+            changes.push(format!("size {} -> {}", describe(&self.size), describe(&new.size)));
+        }
+        for (name, old, new) in &[
+            ("crc", &self.crc, &new.crc),
+            ("md5", &self.md5, &new.md5),
+            ("sha1", &self.sha1, &new.sha1),
+            ("sha256", &self.sha256, &new.sha256),
+        ] {
+            if differs(old, new) {
+                changes.push(format!("{} {} -> {}", name, describe(old), describe(new)));
+            }
+        }
+        changes.join(", ")
+    }
+}
+
+/// Whether both DATs list a value and the values differ.
+fn differs<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> bool {
+    matches!((a, b), (Some(a), Some(b)) if a != b)
+}
+
+fn describe<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(T::to_string)
+        .unwrap_or_else(|| String::from("none"))
+}
+
+/// A game and its ROMs, as far as comparing DATs is concerned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    pub name: String,
+    pub roms: Vec<Rom>,
+}
+
+impl Game {
+    /// The contents of the game regardless of its name, or `None` if the DAT lists no hashes for it.
+    fn fingerprint(&self) -> Option<Vec<String>> {
+        let mut fingerprint = self
+            .roms
+            .iter()
+            .map(Rom::fingerprint)
+            .collect::<Option<Vec<_>>>()?;
+        if fingerprint.is_empty() {
+            return None;
+        }
+        fingerprint.sort();
+        Some(fingerprint)
+    }
+}
+
+fn normalize_hash(value: String) -> Option<String> {
+    Some(value.to_lowercase()).filter(|value| !value.is_empty() && value != "-")
+}
+
+impl From<&dat::Rom> for Rom {
+    fn from(rom: &dat::Rom) -> Self {
+        Rom {
+            name: rom.name.clone(),
+            size: rom.size.as_ref().and_then(|size| size.parse().ok()),
+            crc: rom.crc.clone().and_then(normalize_hash),
+            md5: rom.md5.clone().and_then(normalize_hash),
+            sha1: rom.sha1.clone().and_then(normalize_hash),
+            sha256: rom.sha256.clone().and_then(normalize_hash),
+        }
+    }
+}
+
+impl From<&dat::Game> for Game {
+    fn from(game: &dat::Game) -> Self {
+        Game {
+            name: game.name.clone(),
+            roms: game.roms.iter().chain(game.disks.iter()).map(Rom::from).collect(),
+        }
+    }
+}
+
+/// Reads the games of a Logiqx XML or ClrMamePro DAT.
+pub fn read_games(contents: &str) -> Result<Vec<Game>> {
+    Ok(convert::read_dat(contents)?.games.iter().map(Game::from).collect())
+}
+
+/// Reads the DAT at `path`, which may also be a ZIP archive of exactly one DAT.
+pub fn read_dat<P: AsRef<Path>>(path: P) -> Result<Vec<Game>> {
+    Ok(convert::read_dat_file(path)?.games.iter().map(Game::from).collect())
+}
+
+/// A game that was renamed without changing its ROMs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+}
+
+/// A ROM that was added, removed or changed within a game.
+///
+/// Added ROMs have no `old` ROM, and removed ROMs no `new` ROM.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RomChange {
+    pub name: String,
+    pub old: Option<Rom>,
+    pub new: Option<Rom>,
+}
+
+impl RomChange {
+    fn describe(&self) -> String {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => format!("~ {}: {}", self.name, old.describe_change(new)),
+            (None, _) => format!("+ {}", self.name),
+            (_, None) => format!("- {}", self.name),
+        }
+    }
+}
+
+/// A game whose ROMs changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GameChange {
+    pub name: String,
+    pub roms: Vec<RomChange>,
+}
+
+/// The games that changed between two versions of a DAT.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DatDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub renamed: Vec<Rename>,
+    pub changed: Vec<GameChange>,
+}
+
+impl DatDiff {
+    /// Whether both versions list the same games.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.renamed.is_empty()
+            && self.changed.is_empty()
+    }
+
+    /// A one line count of the changes, such as `2 added, 1 renamed`.
+    pub fn summary(&self) -> String {
+        let counts = [
+            (self.added.len(), "added"),
+            (self.removed.len(), "removed"),
+            (self.renamed.len(), "renamed"),
+            (self.changed.len(), "changed"),
+        ];
+        let summary = counts
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, what)| format!("{} {}", count, what))
+            .collect::<Vec<_>>();
+        if summary.is_empty() {
+            String::from("no changes")
+        } else {
+            summary.join(", ")
+        }
+    }
+}
+
+fn diff_roms(old: &[Rom], new: &[Rom]) -> Vec<RomChange> {
+    let old_roms = old.iter().map(|rom| (&rom.name, rom)).collect::<HashMap<_, _>>();
+    let new_roms = new.iter().map(|rom| (&rom.name, rom)).collect::<HashMap<_, _>>();
+
+    let mut changes = Vec::new();
+    for rom in old {
+        match new_roms.get(&rom.name) {
+            Some(new_rom) if rom.differs(new_rom) => changes.push(RomChange {
+                name: rom.name.clone(),
+                old: Some(rom.clone()),
+                new: Some((*new_rom).clone()),
+            }),
+            Some(_) => {}
+            None => changes.push(RomChange {
+                name: rom.name.clone(),
+                old: Some(rom.clone()),
+                new: None,
+            }),
+        }
+    }
+    for rom in new.iter().filter(|rom| !old_roms.contains_key(&rom.name)) {
+        changes.push(RomChange {
+            name: rom.name.clone(),
+            old: None,
+            new: Some(rom.clone()),
+        });
+    }
+    changes
+}
+
+/// Compares the games of two versions of a DAT.
+///
+/// A game that disappeared while a game with exactly the same ROM hashes appeared is reported
+/// as renamed rather than as removed and added.
+pub fn diff_games(old: &[Game], new: &[Game]) -> DatDiff {
+    let old_names = old.iter().map(|game| &game.name).collect::<HashSet<_>>();
+    let new_games = new.iter().map(|game| (&game.name, game)).collect::<HashMap<_, _>>();
+
+    let mut diff = DatDiff::default();
+    for game in old {
+        if let Some(new_game) = new_games.get(&game.name) {
+            let roms = diff_roms(&game.roms, &new_game.roms);
+            if !roms.is_empty() {
+                diff.changed.push(GameChange {
+                    name: game.name.clone(),
+                    roms,
+                });
+            }
+        }
+    }
+
+    let mut appeared = HashMap::<_, VecDeque<_>>::new();
+    let mut added = Vec::new();
+    for game in new.iter().filter(|game| !old_names.contains(&game.name)) {
+        match game.fingerprint() {
+            Some(fingerprint) => appeared.entry(fingerprint).or_default().push_back(&game.name),
+            None => added.push(&game.name),
+        }
+    }
+
+    for game in old.iter().filter(|game| !new_games.contains_key(&game.name)) {
+        let renamed = game
+            .fingerprint()
+            .and_then(|fingerprint| appeared.get_mut(&fingerprint))
+            .and_then(VecDeque::pop_front);
+        match renamed {
+            Some(to) => diff.renamed.push(Rename {
+                from: game.name.clone(),
+                to: to.clone(),
+            }),
+            None => diff.removed.push(game.name.clone()),
+        }
+    }
+
+    added.extend(appeared.into_values().flatten());
+    diff.added = added.into_iter().cloned().collect();
+    diff.added.sort();
+    diff.removed.sort();
+    diff.renamed.sort_by(|a, b| a.to.cmp(&b.to));
+    diff.changed.sort_by(|a, b| a.name.cmp(&b.name));
+    diff
+}
+
+/// Compares the DATs at `old` and `new`.
+pub fn diff_dats<P: AsRef<Path>, Q: AsRef<Path>>(old: P, new: Q) -> Result<DatDiff> {
+    Ok(diff_games(&read_dat(old)?, &read_dat(new)?))
+}
+
+/// The DATs of a snapshot, keyed by their directory and system.
+#[derive(Default)]
+struct SnapshotDats {
+    dats: BTreeMap<String, Vec<Game>>,
+    /// The DATs that could not be read, keyed like `dats`, with their paths and the reasons.
+    unreadable: BTreeMap<String, String>,
+}
+
+impl SnapshotDats {
+    fn insert(&mut self, key: String, games: Result<Vec<Game>>, path: &str) {
+        let mut unique = key.clone();
+        let mut n = 2;
+        while self.dats.contains_key(&unique) || self.unreadable.contains_key(&unique) {
+            unique = format!("{} ({})", key, n);
+            n += 1;
+        }
+        match games {
+            Ok(games) => {
+                self.dats.insert(unique, games);
+            }
+            Err(e) => {
+                self.unreadable.insert(unique, format!("{}: {}", path, e));
+            }
+        }
+    }
+}
+
+/// Reads the DATs of the ZIP archive at `file`, along with their paths in the archive.
+fn zip_dats(file: &Path) -> Result<Vec<(String, Result<Vec<Game>>)>> {
+    let mut zip = ZipArchive::new(File::open(file)?)?;
+    let mut dats = Vec::new();
+    for i in 0..zip.len() {
+        let mut member = zip.by_index(i)?;
+        if !member.is_file() || !is_dat_name(member.name()) {
+            continue;
+        }
+        let mut contents = Vec::new();
+        let games = match member.read_to_end(&mut contents) {
+            Ok(_) => read_games(&String::from_utf8_lossy(&contents)),
+            Err(e) => Err(e.into()),
+        };
+        dats.push((member.name().to_owned(), games));
+    }
+    Ok(dats)
+}
+
+/// Finds the DATs in a snapshot, keyed by their directory and system.
+///
+/// DATs inside ZIP archives are found as well, so that snapshots of packs can be compared.
+/// DATs and archives that cannot be read are set aside rather than failing the whole snapshot.
+fn snapshot_dats(dir: &Path) -> Result<SnapshotDats> {
+    fn key(dir: &Path, name: &str) -> String {
+        let system = system_name(name);
+        match dir.to_string_lossy().as_ref() {
+            "" => system,
+            dir => format!("{}/{}", dir.replace('\\', "/"), system),
+        }
+    }
+
+    let mut dats = SnapshotDats::default();
+    for file in list_files(dir, &|_| true)? {
+        let relative = relative_path(dir, &file);
+        let relative_path = Path::new(&relative);
+        let parent = relative_path.parent().unwrap_or_else(|| Path::new(""));
+        let filename = relative_path.file_name().unwrap_or_default().to_string_lossy();
+
+        if is_dat_name(&filename) {
+            let games = fs::read(&file)
+                .map_err(Error::from)
+                .and_then(|contents| read_games(&String::from_utf8_lossy(&contents)));
+            dats.insert(key(parent, &filename), games, &relative);
+        } else if filename.to_lowercase().ends_with(".zip") {
+            match zip_dats(&file) {
+                Ok(members) => {
+                    for (member, games) in members {
+                        let member_path = Path::new(&member);
+                        let member_dir = parent.join(member_path.parent().unwrap_or_else(|| Path::new("")));
+                        let member_name = member_path.file_name().unwrap_or_default().to_string_lossy();
+                        let path = format!("{}/{}", relative, member);
+                        dats.insert(key(&member_dir, &member_name), games, &path);
+                    }
+                }
+                Err(e) => {
+                    dats.unreadable.insert(key(parent, &filename), format!("{}: {}", relative, e));
+                }
+            }
+        }
+    }
+    Ok(dats)
+}
+
+/// The DATs that changed between two snapshots.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SnapshotDiff {
+    /// DATs only in the newer snapshot.
+    pub added: Vec<String>,
+    /// DATs only in the older snapshot.
+    pub removed: Vec<String>,
+    /// The changes to the DATs in both snapshots, leaving out unchanged DATs.
+    pub changed: BTreeMap<String, DatDiff>,
+    /// DATs of either snapshot that could not be read and were left out of the comparison,
+    /// with the reasons.
+    pub unreadable: Vec<String>,
+}
+
+/// Compares every DAT in the snapshot directories `old` and `new`.
+///
+/// DATs are matched by their directory and system name, ignoring version tags, so that the
+/// DAT of a system in one snapshot is compared to that of the same system in the other.
+/// DATs that cannot be read in either snapshot are skipped and listed as unreadable.
+pub fn diff_snapshots<P: AsRef<Path>, Q: AsRef<Path>>(old: P, new: Q) -> Result<SnapshotDiff> {
+    let old = snapshot_dats(old.as_ref())?;
+    let mut new = snapshot_dats(new.as_ref())?;
+    let skipped = old
+        .unreadable
+        .keys()
+        .chain(new.unreadable.keys())
+        .cloned()
+        .collect::<HashSet<_>>();
+
+    let mut diff = SnapshotDiff::default();
+    for (key, old_games) in &old.dats {
+        match new.dats.remove(key) {
+            Some(new_games) => {
+                let dat_diff = diff_games(old_games, &new_games);
+                if !dat_diff.is_empty() {
+                    diff.changed.insert(key.clone(), dat_diff);
+                }
+            }
+            None if skipped.contains(key) => {}
+            None => diff.removed.push(key.clone()),
+        }
+    }
+    diff.added = new.dats.into_keys().filter(|key| !skipped.contains(key)).collect();
+    diff.unreadable = old
+        .unreadable
+        .values()
+        .map(|reason| format!("old {}", reason))
+        .chain(new.unreadable.values().map(|reason| format!("new {}", reason)))
+        .collect();
+    Ok(diff)
+}
+
+/// The formats reports can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Text,
+    Markdown,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" | "txt" => Ok(ReportFormat::Text),
+            "markdown" | "md" => Ok(ReportFormat::Markdown),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(anyhow!("Unknown report format {}", s)),
+        }
+    }
+}
+
+impl ReportFormat {
+    /// The file extension of reports in the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Text => "txt",
+            ReportFormat::Markdown => "md",
+            ReportFormat::Json => "json",
+        }
+    }
+}
+
+/// A report that can be written as plain text, Markdown or JSON.
+pub trait Report: Serialize {
+    /// Writes the report as plain text.
+    fn to_text(&self) -> String;
+
+    /// Writes the report as Markdown.
+    fn to_markdown(&self) -> String;
+
+    /// Writes the report in the given format.
+    fn render(&self, format: ReportFormat) -> Result<String> {
+        match format {
+            ReportFormat::Text => Ok(self.to_text()),
+            ReportFormat::Markdown => Ok(self.to_markdown()),
+            ReportFormat::Json => Ok(serde_json::to_string_pretty(self)? + "\n"),
+        }
+    }
+}
+
+impl DatDiff {
+    fn write_text(&self, out: &mut String, indent: &str) {
+        for name in &self.added {
+            let _ = writeln!(out, "{}+ {}", indent, name);
+        }
+        for name in &self.removed {
+            let _ = writeln!(out, "{}- {}", indent, name);
+        }
+        for rename in &self.renamed {
+            let _ = writeln!(out, "{}> {} -> {}", indent, rename.from, rename.to);
+        }
+        for game in &self.changed {
+            let _ = writeln!(out, "{}~ {}", indent, game.name);
+            for rom in &game.roms {
+                let _ = writeln!(out, "{}    {}", indent, rom.describe());
+            }
+        }
+    }
+
+    fn write_markdown(&self, out: &mut String, heading: &str) {
+        let sections: [(&str, Vec<String>); 3] = [
+            ("Added", self.added.iter().map(|name| format!("- {}", name)).collect()),
+            ("Removed", self.removed.iter().map(|name| format!("- {}", name)).collect()),
+            (
+                "Renamed",
+                self.renamed
+                    .iter()
+                    .map(|rename| format!("- {} → {}", rename.from, rename.to))
+                    .collect(),
+            ),
+        ];
+        for (title, lines) in sections.iter().filter(|(_, lines)| !lines.is_empty()) {
+            let _ = writeln!(out, "{} {} ({})\n", heading, title, lines.len());
+            for line in lines {
+                let _ = writeln!(out, "{}", line);
+            }
+            out.push('\n');
+        }
+        if !self.changed.is_empty() {
+            let _ = writeln!(out, "{} Changed ({})\n", heading, self.changed.len());
+            for game in &self.changed {
+                let _ = writeln!(out, "- {}", game.name);
+                for rom in &game.roms {
+                    let _ = writeln!(out, "  - `{}`", rom.describe());
+                }
+            }
+            out.push('\n');
+        }
+    }
+}
+
+impl Report for DatDiff {
+    fn to_text(&self) -> String {
+        let mut out = format!("{}\n", self.summary());
+        self.write_text(&mut out, "");
+        out
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = format!("# DAT changes\n\n{}\n\n", self.summary());
+        self.write_markdown(&mut out, "##");
+        out
+    }
+}
+
+impl Report for SnapshotDiff {
+    fn to_text(&self) -> String {
+        let mut out = String::new();
+        if self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() {
+            out.push_str("no changes\n");
+        }
+        for key in &self.added {
+            let _ = writeln!(out, "+ {}", key);
+        }
+        for key in &self.removed {
+            let _ = writeln!(out, "- {}", key);
+        }
+        for (key, diff) in &self.changed {
+            let _ = writeln!(out, "~ {}: {}", key, diff.summary());
+            diff.write_text(&mut out, "    ");
+        }
+        for reason in &self.unreadable {
+            let _ = writeln!(out, "! {}", reason);
+        }
+        out
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = String::from("# Snapshot changes\n\n");
+        if self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() {
+            out.push_str("No changes.\n");
+        }
+        for (title, keys) in &[
+            ("New DATs", &self.added),
+            ("Removed DATs", &self.removed),
+            ("Unreadable DATs", &self.unreadable),
+        ] {
+            if keys.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "## {} ({})\n", title, keys.len());
+            for key in keys.iter() {
+                let _ = writeln!(out, "- {}", key);
+            }
+            out.push('\n');
+        }
+        for (key, diff) in &self.changed {
+            let _ = writeln!(out, "## {}\n\n{}\n", key, diff.summary());
+            diff.write_markdown(&mut out, "###");
+        }
+        out
+    }
+}
@@ -0,0 +1,766 @@
+use anyhow::{anyhow, Context, Error, Result};
+use bytes::Bytes;
+use chrono::Utc;
+use futures_util::StreamExt;
+use std::fmt;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::stream::Stream;
+use tokio::task;
+use tokio::time::delay_for;
+
+use crate::catalog::{catalog_artifact, diff_catalogs, Catalog, CatalogChanges};
+use crate::diff::{Report, ReportFormat};
+use crate::extract::{extract_zip, verify_zip};
+use crate::organize::Organizer;
+use crate::source::{
+    Artifact, Authentication, DatSource, FetchOptions, LoginRequired, Resume, Validators,
+};
+use crate::state::{
+    dat_version, is_dat_name, relative_path, sha256_file, ArtifactState, Partial, State,
+};
+
+/// How many times an artifact is downloaded before giving up on it.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// How long to wait before downloading an artifact again, for sources that are not throttled.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The directory within the output directory that catalog reports are written to.
+pub const REPORTS_DIR: &str = "reports";
+
+/// Writes the stream to `filename` under `path`, calling `f` with the number of bytes written so far.
+pub async fn do_download<P: AsRef<Path>, F>(
+    path: P,
+    filename: &str,
+    stream: Pin<Box<dyn Stream<Item = Result<Bytes>>>>,
+    f: F,
+) -> Result<u64>
+where
+    F: Fn(u64),
+{
+    resume_download(path, filename, 0, 0, stream, f).await
+}
+
+/// The path a download of `filename` under `path` is written to until it completes.
+pub fn part_path<P: AsRef<Path>>(path: P, filename: &str) -> PathBuf {
+    path.as_ref().join(format!("{}.part", filename))
+}
+
+/// Writes the stream to `filename` under `path`, calling `f` with the number of bytes written so far.
+///
+/// The stream is written to a `.part` file that is only renamed to `filename` once complete and
+/// validated, so a failed download never replaces a previous copy of `filename`.
+/// If `offset` is not 0, the stream continues the first `offset` bytes already in the `.part` file.
+/// If `length` is not 0, the completed download must be exactly `length` bytes long.
+pub async fn resume_download<P: AsRef<Path>, F>(
+    path: P,
+    filename: &str,
+    offset: u64,
+    length: u64,
+    stream: Pin<Box<dyn Stream<Item = Result<Bytes>>>>,
+    f: F,
+) -> Result<u64>
+where
+    F: Fn(u64),
+{
+    let output_path = path.as_ref().join(filename);
+    let part_path = part_path(&path, filename);
+    let mut output = if offset == 0 {
+        File::create(&part_path).await?
+    } else {
+        let mut output = OpenOptions::new().write(true).open(&part_path).await?;
+        if output.metadata().await?.len() < offset {
+            return Err(anyhow!("{:?} is shorter than the resumed download", part_path));
+        }
+        output.set_len(offset).await?;
+        output.seek(SeekFrom::Start(offset)).await?;
+        output
+    };
+
+    let mut written_len: u64 = offset;
+
+    let mut stream = stream;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        output.write_all(&chunk).await?;
+        written_len += chunk.len() as u64;
+        f(written_len);
+    }
+    output.flush().await?;
+    drop(output);
+
+    // Only replace the previous copy once the new one is known to be good.
+    if let Err(e) = validate_download(&part_path, filename, length).await {
+        fs::remove_file(&part_path).await?;
+        return Err(e);
+    }
+    fs::rename(&part_path, &output_path).await?;
+    Ok(written_len)
+}
+
+/// Checks that the downloaded file at `path` is the DAT or archive `filename` claims it is,
+/// rather than an error page or a truncated response.
+///
+/// If `length` is not 0, the file must be exactly `length` bytes long. ZIP archives are opened
+/// and every member is read to check its CRC.
+pub async fn validate_download<P: AsRef<Path>>(path: P, filename: &str, length: u64) -> Result<()> {
+    let actual = fs::metadata(path.as_ref()).await?.len();
+    if length != 0 && actual != length {
+        return Err(anyhow!(
+            "Downloaded {:?} is {} bytes, but the server announced {}",
+            filename,
+            actual,
+            length
+        ));
+    }
+
+    let mut head = Vec::with_capacity(512);
+    File::open(path.as_ref())
+        .await?
+        .take(512)
+        .read_to_end(&mut head)
+        .await?;
+
+    if head.is_empty() {
+        return Err(anyhow!("Downloaded {:?} is empty", filename));
+    }
+    let text = String::from_utf8_lossy(&head).trim_start().to_lowercase();
+    if text.starts_with("<!doctype html") || text.starts_with("<html") {
+        return Err(anyhow!("Downloaded {:?} is an HTML page, not a DAT", filename));
+    }
+    if filename.to_lowercase().ends_with(".zip") {
+        if !(head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06")) {
+            return Err(anyhow!("Downloaded {:?} is not a ZIP archive", filename));
+        }
+        let archive = path.as_ref().to_path_buf();
+        task::spawn_blocking(move || verify_zip(archive))
+            .await?
+            .with_context(|| format!("Downloaded {:?} is corrupt", filename))?;
+    }
+    Ok(())
+}
+
+async fn extract_prefixes(
+    archive: PathBuf,
+    dest: PathBuf,
+    prefixes: Vec<String>,
+) -> Result<Vec<PathBuf>> {
+    let extracted = task::spawn_blocking(move || {
+        extract_zip(&archive, &dest, |name| {
+            prefixes.iter().any(|prefix| name.starts_with(prefix.as_str()))
+        })
+    })
+    .await??;
+
+    if extracted.is_empty() {
+        return Err(anyhow!("None of the requested files were found in the archive"));
+    }
+    Ok(extracted)
+}
+
+/// What a sync did with the artifacts of a source.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Artifacts downloaded for the first time.
+    pub added: Vec<String>,
+    /// Artifacts whose contents changed since they were last downloaded.
+    pub updated: Vec<String>,
+    /// Artifacts that were downloaded again but turned out identical to the saved copy.
+    pub unchanged: Vec<String>,
+    /// Artifacts that were not downloaded because the saved copy is the advertised version,
+    /// or the source reported that they have not changed.
+    pub up_to_date: Vec<String>,
+    /// The DATs that appeared, disappeared or changed version since the previous sync of the
+    /// source, or `None` on the first sync.
+    pub catalog: Option<CatalogChanges>,
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} unchanged, {} up to date",
+            self.added.len(),
+            self.updated.len(),
+            self.unchanged.len(),
+            self.up_to_date.len()
+        )
+    }
+}
+
+/// Progress of [`download_source_with`], reported to its caller as the sync goes.
+#[derive(Debug)]
+pub enum SyncEvent {
+    /// The outcome of authenticating with the source.
+    Authenticated(Authentication),
+    /// The artifact with the given ID requires logging in, so it is skipped.
+    Skipped(String),
+    /// The saved copy of an artifact matches the version the source advertises.
+    UpToDate { id: String, version: String },
+    /// An artifact is requested from the source.
+    Fetching { id: String, version: Option<String> },
+    /// The source reports that the saved copy of an artifact has not changed.
+    NotModified { id: String, version: String },
+    /// The interrupted download of an artifact could not be resumed, so it starts over.
+    Restarting { id: String, error: Error },
+    /// Downloading an artifact failed, and is attempted again.
+    Retrying {
+        id: String,
+        attempt: u32,
+        max_attempts: u32,
+        error: Error,
+    },
+    /// An artifact is saved as `filename`, starting at byte `offset` if an interrupted download is resumed.
+    Saving { filename: String, offset: u64 },
+    /// `written` bytes of `filename` were saved so far, out of `length`, or 0 if unknown.
+    Progress {
+        filename: String,
+        written: u64,
+        length: u64,
+    },
+    /// A DAT was organized at `path` because another DAT had already taken the place at `taken`.
+    Renamed { taken: PathBuf, path: PathBuf },
+    /// DATs were organized from the archive of an artifact.
+    Organized { archive: PathBuf, count: usize },
+    /// Files were extracted from the archive of an artifact.
+    Extracted { archive: PathBuf, count: usize },
+    /// An artifact was downloaded for the first time.
+    Added { id: String, version: String },
+    /// An artifact was downloaded again, but turned out identical to the saved copy.
+    Unchanged { id: String, version: String },
+    /// An artifact changed since it was last downloaded.
+    Updated { id: String, from: String, to: String },
+    /// The sync waits this long before the next artifact, to avoid being throttled.
+    Throttling(Duration),
+    /// Organized DATs that no artifact of the source claims anymore were removed.
+    Pruned(Vec<PathBuf>),
+    /// The DATs of the source were cataloged for the first time.
+    Cataloged(usize),
+    /// The catalog changes of the sync were written to the report at the given path.
+    ReportWritten(PathBuf),
+}
+
+/// Describes the version of a saved artifact for progress reporting.
+fn describe(state: &ArtifactState) -> String {
+    state
+        .dat_version
+        .as_ref()
+        .or(state.version.as_ref())
+        .cloned()
+        .unwrap_or_else(|| format!("sha256 {}", &state.sha256[..12]))
+}
+
+/// Removes the partial download at `part`, if it is still there.
+async fn discard_part(part: &Path) -> Result<()> {
+    match fs::remove_file(part).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// An artifact that was downloaded and verified.
+struct Fetched {
+    filename: String,
+    size: u64,
+    validators: Validators,
+}
+
+/// Downloads the artifact into `dest`, resuming the interrupted download recorded in `state` if possible.
+///
+/// Returns `None` if the source reports that the saved copy has not changed.
+async fn fetch_artifact(
+    source: &dyn DatSource,
+    artifact: &Artifact,
+    saved: Option<&ArtifactState>,
+    state: &mut State,
+    p: &Path,
+    dest: &Path,
+    on_event: &dyn Fn(SyncEvent),
+) -> Result<Option<Fetched>> {
+    let name = source.name();
+    let partial = state.partial(name, &artifact.id).cloned();
+    let resume = match &partial {
+        Some(partial) => fs::metadata(p.join(&partial.path))
+            .await
+            .ok()
+            .map(|part| Resume {
+                offset: part.len(),
+                validators: partial.validators.clone(),
+            }),
+        None => None,
+    };
+    let mut options = FetchOptions {
+        if_modified: saved.map(|saved| saved.validators.clone()),
+        resume,
+    };
+    let download = match source.fetch_with(artifact, &options).await {
+        Err(error) if options.resume.is_some() => {
+            on_event(SyncEvent::Restarting {
+                id: artifact.id.clone(),
+                error,
+            });
+            options.resume = None;
+            source.fetch_with(artifact, &options).await?
+        }
+        download => download?,
+    };
+    let download = match download {
+        Some(download) => download,
+        None => {
+            // The saved copy is current, so an interrupted download of it is of no use anymore.
+            if let Some(partial) = &partial {
+                discard_part(&p.join(&partial.path)).await?;
+                state.set_partial(name, &artifact.id, None);
+                state.save(p).await?;
+            }
+            return Ok(None);
+        }
+    };
+
+    let filename = download.filename;
+    let length = download.length;
+    let part = part_path(dest, &filename);
+    on_event(SyncEvent::Saving {
+        filename: filename.clone(),
+        offset: download.offset,
+    });
+    if download.offset > 0 {
+        // The server may have renamed the artifact since the download was interrupted.
+        let previous_part = partial.as_ref().map(|partial| p.join(&partial.path));
+        if let Some(previous_part) = previous_part.filter(|previous| previous != &part) {
+            fs::rename(previous_part, &part).await?;
+        }
+    } else {
+        // The download restarted, possibly under another name, so the interrupted one is of no use anymore.
+        let previous_part = partial.as_ref().map(|partial| p.join(&partial.path));
+        if let Some(previous_part) = previous_part.filter(|previous| previous != &part) {
+            discard_part(&previous_part).await?;
+        }
+    }
+    state.set_partial(
+        name,
+        &artifact.id,
+        Some(Partial {
+            path: relative_path(p, &part),
+            validators: download.validators.clone(),
+        }),
+    );
+    state.save(p).await?;
+    let size = resume_download(dest, &filename, download.offset, length, download.stream, |written| {
+        on_event(SyncEvent::Progress {
+            filename: filename.clone(),
+            written,
+            length,
+        })
+    })
+    .await?;
+    state.set_partial(name, &artifact.id, None);
+
+    Ok(Some(Fetched {
+        filename,
+        size,
+        validators: download.validators,
+    }))
+}
+
+/// Controls what [`download_source_with`] does with the artifacts it downloads.
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Place the DATs of every artifact into a tree of `<source>/<collection>/<system>.dat`
+    /// under the output directory.
+    pub organize: bool,
+    /// Keep the downloaded archives once their DATs have been extracted or organized.
+    ///
+    /// Archives without any DATs, such as Redump cuesheet packs, are always kept.
+    pub keep_archives: bool,
+    /// Write the catalog changes of every sync that changed any DATs to
+    /// `reports/<source>/<time>` under the output directory, in the given format.
+    pub catalog_report: Option<ReportFormat>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            organize: false,
+            keep_archives: true,
+            catalog_report: None,
+        }
+    }
+}
+
+/// The directory the DATs of a source are organized under, such as `no-intro`.
+pub fn source_dir(name: &str) -> String {
+    name.to_lowercase()
+}
+
+/// Whether the member `name` of an archive is selected by the extraction `prefixes` of its artifact.
+fn is_selected(prefixes: &Option<Vec<String>>, name: &str) -> bool {
+    match prefixes {
+        Some(prefixes) => prefixes.iter().any(|prefix| name.starts_with(prefix.as_str())),
+        None => true,
+    }
+}
+
+/// Places the DATs of the downloaded `archive` with the organizer, on a blocking thread.
+async fn organize_artifact(
+    mut organizer: Organizer,
+    archive: PathBuf,
+    collection: String,
+    prefixes: Option<Vec<String>>,
+) -> Result<(Organizer, Result<Vec<PathBuf>>)> {
+    Ok(task::spawn_blocking(move || {
+        let filename = archive.to_string_lossy().to_lowercase();
+        let organized = if filename.ends_with(".zip") {
+            organizer.organize_zip(&archive, &collection, |name| is_selected(&prefixes, name))
+        } else if is_dat_name(&filename) {
+            organizer.organize_dat(&archive, &collection).map(|dat| vec![dat])
+        } else {
+            Ok(Vec::new())
+        };
+        (organizer, organized)
+    })
+    .await?)
+}
+
+/// Keeps a saved artifact that is not downloaded again, returning its updated state.
+///
+/// Its DATs are reserved with the organizer, and its saved archive is organized if it was downloaded
+/// before organizing was requested.
+async fn keep_saved<P: AsRef<Path>>(
+    mut organizer: Organizer,
+    p: P,
+    artifact: &Artifact,
+    collection: &str,
+    saved: &ArtifactState,
+    options: &SyncOptions,
+    on_event: &dyn Fn(SyncEvent),
+) -> Result<(Organizer, ArtifactState)> {
+    reserve_files(&mut organizer, &p, saved);
+    let mut saved = saved.clone();
+    if options.organize && !saved.organized {
+        let archive = p.as_ref().join(&saved.path);
+        let (returned, organized) = organize_artifact(
+            organizer,
+            archive.clone(),
+            collection.to_owned(),
+            artifact.extract.clone(),
+        )
+        .await?;
+        organizer = returned;
+        let organized = organized?;
+        for (taken, path) in organizer.take_renamed() {
+            on_event(SyncEvent::Renamed { taken, path });
+        }
+        on_event(SyncEvent::Organized {
+            archive: archive.clone(),
+            count: organized.len(),
+        });
+        saved.files = organized.iter().map(|dat| relative_path(&p, dat)).collect();
+        saved.organized = true;
+        if !options.keep_archives && !organized.is_empty() {
+            fs::remove_file(archive).await?;
+        }
+    }
+    saved.dats = saved_dats(&p, artifact, collection, &saved).await?;
+    Ok((organizer, saved))
+}
+
+/// Lists the DATs in the downloaded artifact at `path`, on a blocking thread.
+async fn catalog_downloaded(
+    path: PathBuf,
+    collection: String,
+    prefixes: Option<Vec<String>>,
+    fallback: String,
+) -> Result<Catalog> {
+    task::spawn_blocking(move || {
+        catalog_artifact(&path, &collection, |name| is_selected(&prefixes, name), &fallback)
+    })
+    .await?
+}
+
+/// The DATs in a saved artifact that is not downloaded again.
+///
+/// Artifacts recorded before DATs were cataloged are cataloged from the saved archive, or else from
+/// the files that were extracted or organized from it.
+async fn saved_dats<P: AsRef<Path>>(
+    p: P,
+    artifact: &Artifact,
+    collection: &str,
+    saved: &ArtifactState,
+) -> Result<Catalog> {
+    if !saved.dats.is_empty() {
+        return Ok(saved.dats.clone());
+    }
+    let archive = p.as_ref().join(&saved.path);
+    if fs::metadata(&archive).await.is_ok() {
+        let prefixes = artifact.extract.clone();
+        return catalog_downloaded(archive, collection.to_owned(), prefixes, describe(saved)).await;
+    }
+    let mut dats = Catalog::new();
+    for file in saved.files.iter().map(|file| p.as_ref().join(file)) {
+        // Extracted and organized DATs are stored in a directory named after their collection.
+        let collection = file
+            .parent()
+            .and_then(Path::file_name)
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_else(|| collection.to_owned());
+        dats.extend(catalog_downloaded(file, collection, None, describe(saved)).await?);
+    }
+    Ok(dats)
+}
+
+/// Keeps other artifacts from taking the places of the DATs of a saved artifact that is not downloaded again.
+fn reserve_files<P: AsRef<Path>>(organizer: &mut Organizer, p: P, saved: &ArtifactState) {
+    for file in saved.files.iter() {
+        organizer.reserve(p.as_ref().join(file));
+    }
+}
+
+/// Authenticates with the source and downloads every available artifact into `p`.
+///
+/// Downloads are recorded in the state manifest of `p`. Artifacts whose advertised version
+/// matches the saved copy are skipped, and saved artifacts are only downloaded again if the
+/// source reports that they have changed.
+pub async fn download_source<P: AsRef<Path>>(source: &mut dyn DatSource, p: P) -> Result<SyncReport> {
+    download_source_with(source, p, &SyncOptions::default(), &|_| {}).await
+}
+
+/// Downloads every available artifact of the source into `p` like [`download_source`],
+/// then handles the downloads as described by `options`.
+///
+/// The progress of the sync is reported to `on_event`.
+pub async fn download_source_with<P: AsRef<Path>>(
+    source: &mut dyn DatSource,
+    p: P,
+    options: &SyncOptions,
+    on_event: &dyn Fn(SyncEvent),
+) -> Result<SyncReport> {
+    let name = source.name();
+    let authentication = source.authenticate().await?;
+    let authenticated = matches!(authentication, Authentication::LoggedIn(_));
+    on_event(SyncEvent::Authenticated(authentication));
+
+    let mut state = State::load(&p).await?;
+    let mut report = SyncReport::default();
+
+    source.restore_pages(state.pages.remove(name).unwrap_or_default());
+    let artifacts = source.artifacts().await?;
+    state.pages.insert(name.to_owned(), source.saved_pages());
+    state.save(&p).await?;
+    let mut organizer = Organizer::new(p.as_ref().join(source_dir(name)));
+    let mut catalog = Catalog::new();
+
+    'artifacts: for artifact in artifacts {
+        let previous = state.get(name, &artifact.id).cloned();
+        if artifact.private && !authenticated {
+            on_event(SyncEvent::Skipped(artifact.id.clone()));
+            // The source still offers the DATs of the artifact to those who log in.
+            if let Some(previous) = &previous {
+                reserve_files(&mut organizer, &p, previous);
+                catalog.extend(previous.dats.clone());
+            }
+            continue;
+        }
+
+        let collection = artifact.collection.clone().unwrap_or_else(|| name.to_owned());
+        // A saved artifact whose extraction selection changed is downloaded and extracted again,
+        // as is one that has to be organized but no longer has its archive.
+        let saved = previous.as_ref().filter(|previous| {
+            previous.is_saved(&p)
+                && previous.is_extracted_with(&artifact.extract)
+                && previous.is_organized_with(&p, options.organize)
+        });
+        let dest = match &artifact.dir {
+            Some(dir) => p.as_ref().join(dir),
+            None => p.as_ref().to_path_buf(),
+        };
+        let up_to_date =
+            saved.filter(|saved| artifact.version.is_some() && artifact.version == saved.version);
+        let fetched = match up_to_date {
+            Some(saved) => {
+                on_event(SyncEvent::UpToDate {
+                    id: artifact.id.clone(),
+                    version: describe(saved),
+                });
+                None
+            }
+            None => {
+                fs::create_dir_all(&dest).await?;
+                on_event(SyncEvent::Fetching {
+                    id: artifact.id.clone(),
+                    version: artifact.version.clone(),
+                });
+                let mut attempt = 1;
+                let fetched = loop {
+                    let fetched =
+                        fetch_artifact(&*source, &artifact, saved, &mut state, p.as_ref(), &dest, on_event);
+                    match fetched.await {
+                        Err(e) if !authenticated && e.is::<LoginRequired>() => {
+                            on_event(SyncEvent::Skipped(artifact.id.clone()));
+                            if let Some(previous) = &previous {
+                                reserve_files(&mut organizer, &p, previous);
+                                catalog.extend(previous.dats.clone());
+                            }
+                            continue 'artifacts;
+                        }
+                        Err(error) if attempt < MAX_ATTEMPTS => {
+                            on_event(SyncEvent::Retrying {
+                                id: artifact.id.clone(),
+                                attempt,
+                                max_attempts: MAX_ATTEMPTS,
+                                error,
+                            });
+                            delay_for(source.throttle().unwrap_or(RETRY_DELAY)).await;
+                            attempt += 1;
+                        }
+                        fetched => break fetched?,
+                    }
+                };
+                if let (None, Some(saved)) = (&fetched, saved) {
+                    on_event(SyncEvent::NotModified {
+                        id: artifact.id.clone(),
+                        version: describe(saved),
+                    });
+                }
+                fetched
+            }
+        };
+        let Fetched { filename, size, validators } = match fetched {
+            Some(fetched) => fetched,
+            None => {
+                let saved = saved.expect("only saved artifacts are requested conditionally");
+                let (returned, saved) =
+                    keep_saved(organizer, &p, &artifact, &collection, saved, options, on_event).await?;
+                organizer = returned;
+                catalog.extend(saved.dats.clone());
+                state.insert(name, &artifact.id, saved);
+                report.up_to_date.push(artifact.id);
+                continue;
+            }
+        };
+
+        let archive = dest.join(&filename);
+        let (sha256, dat_version) = {
+            let archive = archive.clone();
+            task::spawn_blocking(move || -> Result<_> {
+                Ok((sha256_file(&archive)?, dat_version(&archive).unwrap_or(None)))
+            })
+            .await??
+        };
+        let mut current = ArtifactState {
+            url: artifact.url.clone(),
+            fetched_at: Utc::now(),
+            path: relative_path(&p, &archive),
+            size,
+            sha256,
+            validators,
+            version: artifact.version.clone(),
+            dat_version,
+            files: Vec::new(),
+            extract: artifact.extract.clone(),
+            organized: options.organize,
+            dats: Catalog::new(),
+        };
+        current.dats = catalog_downloaded(
+            archive.clone(),
+            collection.clone(),
+            artifact.extract.clone(),
+            describe(&current),
+        )
+        .await?;
+        catalog.extend(current.dats.clone());
+
+        if options.organize {
+            let (returned, organized) =
+                organize_artifact(organizer, archive.clone(), collection, artifact.extract.clone())
+                    .await?;
+            organizer = returned;
+            let organized = organized?;
+            for (taken, path) in organizer.take_renamed() {
+                on_event(SyncEvent::Renamed { taken, path });
+            }
+            on_event(SyncEvent::Organized {
+                archive: archive.clone(),
+                count: organized.len(),
+            });
+            current.files = organized.iter().map(|dat| relative_path(&p, dat)).collect();
+            if !options.keep_archives && !organized.is_empty() {
+                fs::remove_file(archive).await?;
+            }
+        } else if let Some(prefixes) = artifact.extract {
+            let extracted = extract_prefixes(archive.clone(), dest, prefixes).await?;
+            on_event(SyncEvent::Extracted {
+                archive: archive.clone(),
+                count: extracted.len(),
+            });
+            current.files = extracted.iter().map(|file| relative_path(&p, file)).collect();
+            if !options.keep_archives && !extracted.is_empty() {
+                fs::remove_file(archive).await?;
+            }
+        }
+
+        match previous {
+            None => {
+                on_event(SyncEvent::Added {
+                    id: artifact.id.clone(),
+                    version: describe(&current),
+                });
+                report.added.push(artifact.id.clone());
+            }
+            Some(previous) if previous.sha256 == current.sha256 => {
+                on_event(SyncEvent::Unchanged {
+                    id: artifact.id.clone(),
+                    version: describe(&current),
+                });
+                report.unchanged.push(artifact.id.clone());
+            }
+            Some(previous) => {
+                on_event(SyncEvent::Updated {
+                    id: artifact.id.clone(),
+                    from: describe(&previous),
+                    to: describe(&current),
+                });
+                report.updated.push(artifact.id.clone());
+            }
+        }
+        state.insert(name, &artifact.id, current);
+        state.save(&p).await?;
+
+        if let Some(throttle) = source.throttle() {
+            on_event(SyncEvent::Throttling(throttle));
+            delay_for(throttle).await;
+        }
+    }
+
+    if options.organize {
+        let pruned = task::spawn_blocking(move || organizer.prune()).await??;
+        if !pruned.is_empty() {
+            on_event(SyncEvent::Pruned(pruned));
+        }
+    }
+
+    if let Some(previous) = state.catalogs.insert(name.to_owned(), catalog.clone()) {
+        let changes = diff_catalogs(name, &previous, &catalog);
+        if let Some(format) = options.catalog_report.filter(|_| !changes.is_empty()) {
+            let dir = p.as_ref().join(REPORTS_DIR).join(source_dir(name));
+            fs::create_dir_all(&dir).await?;
+            let path = dir.join(format!(
+                "{}.{}",
+                Utc::now().format("%Y-%m-%dT%H%M%SZ"),
+                format.extension()
+            ));
+            fs::write(&path, changes.render(format)?).await?;
+            on_event(SyncEvent::ReportWritten(path));
+        }
+        report.catalog = Some(changes);
+    } else {
+        on_event(SyncEvent::Cataloged(catalog.len()));
+    }
+    state.save(&p).await?;
+    Ok(report)
+}
@@ -0,0 +1,73 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// Extracts the files in the ZIP archive at `archive` that match `filter` into `dest`,
+/// keeping their paths within the archive.
+///
+/// Each file replaces any previous copy only once it has been extracted in full.
+///
+/// Returns the paths of the extracted files.
+pub fn extract_zip<P, Q, F>(archive: P, dest: Q, filter: F) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: Fn(&str) -> bool,
+{
+    let mut zip = ZipArchive::new(File::open(archive.as_ref())?)?;
+    let mut extracted = Vec::new();
+
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        if !file.is_file() || !filter(file.name()) {
+            continue;
+        }
+
+        let name = file
+            .enclosed_name()
+            .ok_or_else(|| anyhow!("Archive contains an unsafe path: {}", file.name()))?
+            .to_owned();
+        let output_path = dest.as_ref().join(name);
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        write_atomically(&mut file, &output_path)?;
+        extracted.push(output_path);
+    }
+
+    Ok(extracted)
+}
+
+/// Copies `reader` to `output_path`, replacing any previous file only once the copy is complete.
+///
+/// The copy is written next to the destination first, so a failure never leaves a truncated file behind.
+pub fn write_atomically<R: Read>(reader: &mut R, output_path: &Path) -> Result<()> {
+    let mut temp_name = output_path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(".part");
+    let temp_path = output_path.with_file_name(temp_name);
+    let copied = File::create(&temp_path).and_then(|mut output| io::copy(reader, &mut output));
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    fs::rename(&temp_path, output_path)?;
+    Ok(())
+}
+
+/// Checks the central directory of the ZIP archive at `archive` and the CRC of every file in it.
+///
+/// Returns the number of files in the archive.
+pub fn verify_zip<P: AsRef<Path>>(archive: P) -> Result<usize> {
+    let mut zip = ZipArchive::new(File::open(archive.as_ref())?)?;
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        let name = file.name().to_owned();
+        // Reading a file to the end makes the zip crate check its CRC.
+        io::copy(&mut file, &mut io::sink())
+            .with_context(|| format!("{} failed its integrity check", name))?;
+    }
+    Ok(zip.len())
+}
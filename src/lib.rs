@@ -0,0 +1,32 @@
+//! Respectful DAT downloader for use with shiratsu.
+//!
+//! Each supported DAT provider is exposed as a [`DatSource`], which can be driven
+//! to disk with [`download_source`].
+
+pub mod catalog;
+pub mod convert;
+pub mod dat;
+pub mod diff;
+pub mod download;
+pub mod extract;
+pub mod nointro;
+pub mod organize;
+pub mod redump;
+pub mod snapshot;
+pub mod source;
+pub mod state;
+pub mod tosec;
+
+pub use convert::convert_to_xml_dat;
+pub use download::{
+    do_download, download_source, download_source_with, resume_download, validate_download,
+    SyncEvent, SyncOptions, SyncReport,
+};
+pub use source::{Artifact, Authentication, DatSource, Download};
+
+/// Login credentials for sources that provide private DATs to authenticated users.
+#[derive(Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
@@ -1,55 +1,241 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use atsumare::convert::{read_dat_file, write_dat};
+use atsumare::dat::Dialect;
+use atsumare::diff::{diff_dats, diff_snapshots, Report, ReportFormat};
+use atsumare::snapshot::{prune_snapshots, take_snapshot, update_latest, Retention};
+use atsumare::{
+    download_source_with, nointro, redump, tosec, Authentication, Credentials, DatSource, SyncEvent,
+    SyncOptions,
+};
+use chrono::{Duration, Utc};
 use clap::*;
+use regex::Regex;
 
 use std::env::var;
-use std::path::{Path, PathBuf};
-use std::pin::Pin;
-use std::time::Duration;
-
-mod nointro;
-mod redump;
-mod tosec;
-mod convert;
+use std::io::Write;
+use std::path::PathBuf;
 
 const ATSUMARE_DOM_USER: &str = "ATSUMARE_DOM_USER";
 const ATSUMARE_DOM_PASS: &str = "ATSUMARE_DOM_PASS";
 const ATSUMARE_REDUMP_USER: &str = "ATSUMARE_REDUMP_USER";
 const ATSUMARE_REDUMP_PASS: &str = "ATSUMARE_REDUMP_PASS";
 
-use bytes::Bytes;
-use futures_util::StreamExt;
-use nointro::Prepare;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
-use tokio::stream::Stream;
-use tokio::time::delay_for;
+const REDUMP_VARIANTS: [&str; 4] = ["plain", "serial", "version", "serial,version"];
+const REDUMP_PACKS: [&str; 5] = ["datfile", "cues", "sbi", "gdi", "keys"];
+const REPORT_FORMATS: [&str; 3] = ["text", "markdown", "json"];
+const DIALECTS: [&str; 3] = ["logiqx", "clrmamepro", "romcenter"];
 
-#[derive(Debug)]
-enum Sources {
-    NoIntro(Option<Credentials>),
-    Redump(Option<Credentials>),
-    TOSEC,
+struct Options {
+    output_dir: PathBuf,
+    sources: Vec<Box<dyn DatSource>>,
+    sync: SyncOptions,
+    snapshot: bool,
+    retention: Retention,
 }
 
-#[derive(Debug)]
-pub struct Credentials {
-    pub username: String,
-    pub password: String,
+struct DiffOptions {
+    old: PathBuf,
+    new: PathBuf,
+    format: ReportFormat,
 }
-#[derive(Debug)]
-struct Options {
-    output_dir: PathBuf,
-    sources: Vec<Sources>,
+
+struct ConvertOptions {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    dialect: Dialect,
+}
+
+enum Command {
+    Sync(Options),
+    ListNoIntroSystems(nointro::NoIntro),
+    Diff(DiffOptions),
+    Convert(ConvertOptions),
+}
+
+fn validate_regex(pattern: String) -> std::result::Result<(), String> {
+    Regex::new(&pattern).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn parse_sections(value: &str) -> Result<Vec<nointro::Section>> {
+    value.split(',').map(|section| section.trim().parse()).collect()
+}
+
+fn validate_sections(value: String) -> std::result::Result<(), String> {
+    parse_sections(&value).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn validate_count(value: String) -> std::result::Result<(), String> {
+    value.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn validate_system_id(value: String) -> std::result::Result<(), String> {
+    value.parse::<u32>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn parse_system_variant(value: &str) -> Result<(String, redump::DatVariant)> {
+    let mut parts = value.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(slug), Some(variant)) if !slug.is_empty() => Ok((slug.to_owned(), variant.parse()?)),
+        _ => Err(anyhow!("Expected SLUG=VARIANT, got {}", value)),
+    }
+}
+
+fn validate_system_variant(value: String) -> std::result::Result<(), String> {
+    parse_system_variant(&value).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn values(matches: &ArgMatches, name: &str) -> Vec<String> {
+    matches
+        .values_of(name)
+        .map(|values| values.map(|v| v.to_owned()).collect())
+        .unwrap_or_default()
+}
+
+fn regexes(matches: &ArgMatches, name: &str) -> Vec<Regex> {
+    values(matches, name)
+        .iter()
+        .map(|pattern| Regex::new(pattern).expect("pattern was validated by clap"))
+        .collect()
+}
+
+fn nointro_source(matches: &ArgMatches) -> nointro::NoIntro {
+    let creds = var(ATSUMARE_DOM_USER)
+        .and_then(|username| {
+            var(ATSUMARE_DOM_PASS).map(|password| Credentials { username, password })
+        })
+        .ok();
+
+    let mut source = nointro::NoIntro::new(creds);
+    if let Some(packs) = matches.values_of("nointro-section") {
+        source = source.with_sections(
+            packs
+                .map(|pack| parse_sections(pack).expect("sections were validated by clap"))
+                .collect(),
+        );
+    }
+    if let Some(systems) = matches.values_of("nointro-system") {
+        source = source.with_systems(
+            systems
+                .map(|id| id.parse().expect("system ID was validated by clap"))
+                .collect(),
+        );
+        if !matches.is_present("nointro-section") {
+            source = source.with_sections(vec![]);
+        }
+    }
+    if let Some(dat_types) = matches.values_of("nointro-dat-type") {
+        source = source.with_dat_types(
+            dat_types
+                .map(|t| t.parse().expect("DAT type was validated by clap"))
+                .collect(),
+        );
+    }
+    if let Some(root) = matches.value_of("nointro-url") {
+        source = source.with_endpoints(nointro::Endpoints::default().with_root(root));
+    }
+    source
 }
 
-fn get_matches() -> Options {
+fn get_matches() -> Command {
     let matches = App::new("atsumare")
         .version(crate_version!())
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare the games of two DATs, or every DAT of two snapshots")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&REPORT_FORMATS)
+                        .default_value("text")
+                        .help("The format of the report"),
+                )
+                .arg(
+                    Arg::with_name("old")
+                        .required(true)
+                        .help("The older DAT or snapshot directory")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .required(true)
+                        .help("The newer DAT or snapshot directory")
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("convert")
+                .about("Convert a DAT to another dialect")
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&DIALECTS)
+                        .value_name("DIALECT")
+                        .help("The dialect to convert the DAT to"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .required(true)
+                        .help("The DAT to convert, or a ZIP archive of exactly one DAT")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .help("Where to write the converted DAT. Defaults to standard output")
+                        .index(2),
+                ),
+        )
         .arg(
             Arg::with_name("nointro")
                 .long("nointro")
                 .help("Download DATs from No-Intro DAT-o-Matic"),
         )
+        .arg(
+            Arg::with_name("nointro-dat-type")
+                .long("nointro-dat-type")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&["standard", "parent-clone"])
+                .value_name("TYPE")
+                .help(
+                    "The type of No-Intro DAT to download: standard or parent-clone (Parent/Clone XML). \
+                     Other DAT-o-Matic types are not supported. May be repeated. Defaults to standard",
+                ),
+        )
+        .arg(
+            Arg::with_name("nointro-section")
+                .long("nointro-section")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(validate_sections)
+                .value_name("SECTIONS")
+                .help("Comma separated No-Intro sections to combine into one daily pack, \
+                       such as nointro,nongame. May be repeated to download separate packs. \
+                       Sections are nointro, nongame, unofficial, nonredump, aftermarket or the label of \
+                       any other section on the daily download page"),
+        )
+        .arg(
+            Arg::with_name("nointro-system")
+                .long("nointro-system")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(validate_system_id)
+                .value_name("ID")
+                .help("Download the DAT of the No-Intro system with the given ID individually. \
+                       May be repeated. Skips the daily pack unless --nointro-section is also given"),
+        )
+        .arg(
+            Arg::with_name("nointro-list-systems")
+                .long("nointro-list-systems")
+                .help("Print the ID, section and name of each No-Intro system, then exit. \
+                       Uses the No-Intro credentials and --nointro-url if given"),
+        )
         .arg(
             Arg::with_name("tosec")
                 .long("tosec")
@@ -62,35 +248,253 @@ fn get_matches() -> Options {
         )
         .group(
             ArgGroup::with_name("sources")
-                .args(&["nointro", "tosec", "redump"])
+                .args(&["nointro", "tosec", "redump", "nointro-list-systems"])
                 .required(true)
                 .multiple(true),
         )
+        .arg(
+            Arg::with_name("nointro-url")
+                .long("nointro-url")
+                .takes_value(true)
+                .value_name("URL")
+                .help("Override the DAT-o-Matic site root"),
+        )
+        .arg(
+            Arg::with_name("tosec-url")
+                .long("tosec-url")
+                .takes_value(true)
+                .value_name("URL")
+                .help("Override the TOSEC site root"),
+        )
+        .arg(
+            Arg::with_name("tosec-release")
+                .long("tosec-release")
+                .takes_value(true)
+                .value_name("DATE")
+                .help("Download the TOSEC release from the given date (YYYY-MM-DD) instead of the newest"),
+        )
+        .arg(
+            Arg::with_name("tosec-collection")
+                .long("tosec-collection")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&["TOSEC", "TOSEC-ISO", "TOSEC-PIX"])
+                .value_name("COLLECTION")
+                .help("Extract only the DATs of the given TOSEC collection. May be repeated"),
+        )
+        .arg(
+            Arg::with_name("redump-system")
+                .long("redump-system")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("SLUG")
+                .help("Download only the given Redump system, such as psx. May be repeated"),
+        )
+        .arg(
+            Arg::with_name("redump-exclude-system")
+                .long("redump-exclude-system")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("SLUG")
+                .help("Skip the given Redump system. May be repeated"),
+        )
+        .arg(
+            Arg::with_name("redump-system-name")
+                .long("redump-system-name")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(validate_regex)
+                .value_name("PATTERN")
+                .help("Download only Redump systems whose name matches the pattern. May be repeated"),
+        )
+        .arg(
+            Arg::with_name("redump-exclude-system-name")
+                .long("redump-exclude-system-name")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(validate_regex)
+                .value_name("PATTERN")
+                .help("Skip Redump systems whose name matches the pattern. May be repeated"),
+        )
+        .arg(
+            Arg::with_name("redump-variant")
+                .long("redump-variant")
+                .takes_value(true)
+                .possible_values(&REDUMP_VARIANTS)
+                .value_name("VARIANT")
+                .help("The Redump DAT variant to download. Defaults to serial,version"),
+        )
+        .arg(
+            Arg::with_name("redump-system-variant")
+                .long("redump-system-variant")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(validate_system_variant)
+                .value_name("SLUG=VARIANT")
+                .help("The Redump DAT variant to download for the given system. May be repeated"),
+        )
+        .arg(
+            Arg::with_name("redump-pack")
+                .long("redump-pack")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&REDUMP_PACKS)
+                .value_name("KIND")
+                .help("The kind of Redump pack to download. May be repeated. Defaults to datfile"),
+        )
+        .arg(
+            Arg::with_name("redump-url")
+                .long("redump-url")
+                .takes_value(true)
+                .value_name("URL")
+                .help("Override the Redump site root"),
+        )
+        .arg(
+            Arg::with_name("redump-forum-url")
+                .long("redump-forum-url")
+                .takes_value(true)
+                .value_name("URL")
+                .help("Override the Redump forum root used to log in"),
+        )
+        .arg(
+            Arg::with_name("organize")
+                .long("organize")
+                .help("Extract the DATs of every download into outdir/<source>/<collection>/<system>.dat"),
+        )
+        .arg(
+            Arg::with_name("delete-archives")
+                .long("delete-archives")
+                .help("Delete downloaded archives once their DATs have been extracted or organized"),
+        )
+        .arg(
+            Arg::with_name("catalog-report")
+                .long("catalog-report")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&REPORT_FORMATS)
+                .help("Write the DATs that appeared, disappeared or changed version in each source to outdir/reports"),
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .long("snapshot")
+                .requires("organize")
+                .help("Store a dated snapshot of the organized DATs after syncing, and update outdir/latest \
+                       with the newest DAT of each system"),
+        )
+        .arg(
+            Arg::with_name("keep-snapshots")
+                .long("keep-snapshots")
+                .takes_value(true)
+                .value_name("N")
+                .validator(validate_count)
+                .requires("snapshot")
+                .help("Prune all but the N newest snapshots, besides those kept by --keep-monthly"),
+        )
+        .arg(
+            Arg::with_name("keep-monthly")
+                .long("keep-monthly")
+                .takes_value(true)
+                .value_name("N")
+                .validator(validate_count)
+                .requires("snapshot")
+                .help("Keep the newest snapshot of each of the N most recent months when pruning"),
+        )
+        .arg(
+            Arg::with_name("max-snapshot-age")
+                .long("max-snapshot-age")
+                .takes_value(true)
+                .value_name("DAYS")
+                .validator(validate_count)
+                .requires("snapshot")
+                .help("Prune snapshots older than DAYS days"),
+        )
         .arg(
             Arg::with_name("outdir")
-                .required(true)
+                .required_unless("nointro-list-systems")
                 .help("The output directory")
                 .index(1),
         )
         .get_matches();
 
+    if let Some(diff) = matches.subcommand_matches("diff") {
+        return Command::Diff(DiffOptions {
+            old: PathBuf::from(diff.value_of("old").expect("old is required")),
+            new: PathBuf::from(diff.value_of("new").expect("new is required")),
+            format: diff
+                .value_of("format")
+                .expect("format has a default")
+                .parse()
+                .expect("format was validated by clap"),
+        });
+    }
+
+    if let Some(convert) = matches.subcommand_matches("convert") {
+        return Command::Convert(ConvertOptions {
+            input: PathBuf::from(convert.value_of("input").expect("input is required")),
+            output: convert.value_of("output").map(PathBuf::from),
+            dialect: convert
+                .value_of("to")
+                .expect("to is required")
+                .parse()
+                .expect("dialect was validated by clap"),
+        });
+    }
+
+    if matches.is_present("nointro-list-systems") {
+        return Command::ListNoIntroSystems(nointro_source(&matches));
+    }
+
     let mut options = Options {
         output_dir: PathBuf::from(matches.value_of("outdir").unwrap_or("unsorted").to_owned()),
         sources: vec![],
+        sync: SyncOptions {
+            organize: matches.is_present("organize"),
+            keep_archives: !matches.is_present("delete-archives"),
+            catalog_report: matches
+                .value_of("catalog-report")
+                .map(|format| format.parse().expect("format was validated by clap")),
+        },
+        snapshot: matches.is_present("snapshot"),
+        retention: Retention {
+            keep_last: matches
+                .value_of("keep-snapshots")
+                .map(|n| n.parse().expect("count was validated by clap")),
+            keep_monthly: matches
+                .value_of("keep-monthly")
+                .map(|n| n.parse().expect("count was validated by clap")),
+            max_age: matches
+                .value_of("max-snapshot-age")
+                .map(|days| Duration::days(days.parse().expect("count was validated by clap"))),
+        },
     };
 
     if matches.is_present("nointro") {
-        let creds = var(ATSUMARE_DOM_USER)
-            .and_then(|username| {
-                var(ATSUMARE_DOM_PASS).map(|password| Credentials { username, password })
-            })
-            .ok();
-
-        options.sources.push(Sources::NoIntro(creds))
+        options.sources.push(Box::new(nointro_source(&matches)))
     }
 
     if matches.is_present("tosec") {
-        options.sources.push(Sources::TOSEC)
+        let mut source = tosec::Tosec::new();
+        if let Some(root) = matches.value_of("tosec-url") {
+            source = source.with_endpoints(tosec::Endpoints::default().with_root(root));
+        }
+        if let Some(date) = matches.value_of("tosec-release") {
+            source = source.with_release(date);
+        }
+        if let Some(collections) = matches.values_of("tosec-collection") {
+            source = source.with_collections(
+                collections
+                    .map(|c| c.parse().expect("collection was validated by clap"))
+                    .collect(),
+            );
+        }
+        options.sources.push(Box::new(source))
     }
 
     if matches.is_present("redump") {
@@ -99,134 +503,185 @@ fn get_matches() -> Options {
                 var(ATSUMARE_REDUMP_PASS).map(|password| Credentials { username, password })
             })
             .ok();
-        options.sources.push(Sources::Redump(creds))
-    }
-
-    options
-}
-
-async fn do_download<P: AsRef<Path>, F>(
-    path: P,
-    filename: &str,
-    stream: Pin<Box<dyn Stream<Item = Result<Bytes>>>>,
-    f: F,
-) -> Result<u64>
-where
-    F: Fn(u64) -> (),
-{
-    let mut output_path = path.as_ref().to_path_buf();
-    output_path.push(filename);
-    let mut output = File::create(&output_path).await?;
-
-    let mut written_len: u64 = 0;
-
-    let mut stream = stream;
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk?;
-        output.write_all(&chunk).await?;
-        written_len += chunk.len() as u64;
-        f(written_len);
-    }
-
-    Ok(written_len)
-}
-
-async fn download_nointro<P: AsRef<Path>>(c: Option<Credentials>, p: P) -> Result<()> {
-    let session: Option<String>;
-    let mut prepares = Vec::new();
-    prepares.push(Prepare::public());
-
-    if let Some(credentials) = c {
-        match nointro::fetch_authenticated_session(&credentials)
-            .await
-            .ok()
-        {
-            Some(logged_in) => {
-                session = Some(logged_in);
-                println!("No-Intro: Logged in as {}.", credentials.username);
-                
-                // DAT-o-matic requires a valid login to properly generate a private Daily DAT pack
-                prepares.push(Prepare::private());
-            }
-            None => {
-                println!("No-Intro: Invalid credentials.");
-                session = None;
-            }
+        let mut endpoints = redump::Endpoints::default();
+        if let Some(root) = matches.value_of("redump-url") {
+            endpoints = endpoints.with_root(root);
+        }
+        if let Some(forum) = matches.value_of("redump-forum-url") {
+            endpoints = endpoints.with_forum(forum);
+        }
+        let filter = redump::SystemFilter {
+            include: values(&matches, "redump-system"),
+            exclude: values(&matches, "redump-exclude-system"),
+            include_names: regexes(&matches, "redump-system-name"),
+            exclude_names: regexes(&matches, "redump-exclude-system-name"),
+        };
+        let kinds = match matches.values_of("redump-pack") {
+            Some(kinds) => kinds
+                .map(|kind| kind.parse().expect("pack kind was validated by clap"))
+                .collect(),
+            None => vec![redump::PackKind::Datfile],
+        };
+        let mut variants = redump::Variants::default();
+        if let Some(variant) = matches.value_of("redump-variant") {
+            variants.default = variant.parse().expect("variant was validated by clap");
         }
-    } else {
-        println!("No-Intro: Downloading unauthenticated");
-        session = None
+        for system_variant in values(&matches, "redump-system-variant") {
+            let (slug, variant) = parse_system_variant(&system_variant)
+                .expect("variant was validated by clap");
+            variants.systems.insert(slug, variant);
+        }
+        options.sources.push(Box::new(
+            redump::Redump::new(creds)
+                .with_endpoints(endpoints)
+                .with_filter(filter)
+                .with_variants(variants)
+                .with_kinds(kinds),
+        ))
     }
 
-    for prepare in prepares {
-        let (download_url, session) = nointro::fetch_download_url(&prepare, &session).await?;
+    Command::Sync(options)
+}
 
-        let (filename, length, stream) = nointro::fetch_zip(download_url, session).await?;
-        println!("DAT-o-Matic: Saving {:?}..", filename);
-        do_download(&p, &filename, stream, |f| {
-            println!("{:?}: {} of {}", filename, f, length)
-        })
-        .await?;
-        println!("DAT-o-Matic: Waiting 30 seconds to avoid throttling...");
-        delay_for(Duration::new(30, 0)).await;
-    }
+fn diff(options: &DiffOptions) -> Result<()> {
+    let report = match (options.old.is_dir(), options.new.is_dir()) {
+        (true, true) => diff_snapshots(&options.old, &options.new)?.render(options.format)?,
+        (false, false) => diff_dats(&options.old, &options.new)?.render(options.format)?,
+        _ => {
+            return Err(anyhow!(
+                "Unable to compare {:?} to {:?}: both must be DATs or both snapshots",
+                options.old,
+                options.new
+            ))
+        }
+    };
+    print!("{}", report);
     Ok(())
 }
 
-async fn download_tosec<P: AsRef<Path>>(p: P) -> Result<()> {
-    let (filename, length, stream) = tosec::fetch_zip().await?;
-    println!("TOSEC: Saving {:?}..", filename);
-    do_download(&p, &filename, stream, |f| {
-        println!("{:?}: {} of {}", filename, f, length)
-    })
-    .await?;
+fn convert(options: &ConvertOptions) -> Result<()> {
+    let dat = write_dat(&read_dat_file(&options.input)?, options.dialect)?;
+    match &options.output {
+        Some(output) => std::fs::write(output, &dat)?,
+        None => std::io::stdout().write_all(&dat)?,
+    }
     Ok(())
 }
 
-async fn download_redump<P: AsRef<Path>>(c: Option<Credentials>, p: P) -> Result<()> {
-    let session: Option<String>;
-    if let Some(credentials) = c {
-        match redump::fetch_authenticated_session(&credentials).await.ok() {
-            Some(logged_in) => {
-                session = Some(logged_in);
-                println!("Redump: Logged in as {}.", credentials.username);
-            }
-            None => {
-                println!("Redump: Invalid credentials.");
-                session = None;
-            }
-        }
-    } else {
-        println!("Redump: Downloading unauthenticated");
-        session = None
+async fn list_nointro_systems(mut source: nointro::NoIntro) -> Result<()> {
+    if let Authentication::InvalidCredentials = source.authenticate().await? {
+        eprintln!("{}: Invalid credentials, listing systems unauthenticated", source.name());
     }
-
-    let anchors = redump::fetch_download_urls(&session).await?;
-    for anchor in anchors {
-        let (filename, length, stream) = redump::fetch_zip(anchor, &session).await?;
-        println!("Redump: Saving {:?}..", filename);
-        do_download(&p, &filename, stream, |f| {
-            println!("{:?}: {} of {}", filename, f, length)
-        })
-        .await?;
+    for system in source.systems().await? {
+        let section = system.section.unwrap_or(nointro::Section::NoIntro);
+        println!("{}\t{}\t{}", system.id, section.label(), system.name);
     }
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let matches = get_matches();
-    
+/// Prints the progress of syncing the source `name`.
+fn print_event(name: &str, event: SyncEvent) {
+    match event {
+        SyncEvent::Authenticated(Authentication::LoggedIn(username)) => {
+            println!("{}: Logged in as {}.", name, username)
+        }
+        SyncEvent::Authenticated(Authentication::InvalidCredentials) => {
+            println!("{}: Invalid credentials.", name)
+        }
+        SyncEvent::Authenticated(Authentication::Anonymous) => {
+            println!("{}: Downloading unauthenticated", name)
+        }
+        SyncEvent::Authenticated(Authentication::Unsupported) => {}
+        SyncEvent::Skipped(id) => println!("{}: Skipping {}, which requires logging in", name, id),
+        SyncEvent::UpToDate { id, version } => println!("{}: {} is up to date ({})", name, id, version),
+        SyncEvent::Fetching { id, version: Some(version) } => {
+            println!("{}: Fetching {} ({})..", name, id, version)
+        }
+        SyncEvent::Fetching { id, version: None } => println!("{}: Fetching {}..", name, id),
+        SyncEvent::NotModified { id, version } => {
+            println!("{}: {} has not changed ({})", name, id, version)
+        }
+        SyncEvent::Restarting { id, error } => {
+            println!("{}: Unable to resume {}, restarting: {}", name, id, error)
+        }
+        SyncEvent::Retrying { id, attempt, max_attempts, error } => println!(
+            "{}: Downloading {} failed, retrying ({} of {}): {}",
+            name, id, attempt, max_attempts, error
+        ),
+        SyncEvent::Saving { filename, offset: 0 } => println!("{}: Saving {:?}..", name, filename),
+        SyncEvent::Saving { filename, offset } => {
+            println!("{}: Resuming {:?} from byte {}..", name, filename, offset)
+        }
+        SyncEvent::Progress { filename, written, length } => {
+            println!("{:?}: {} of {}", filename, written, length)
+        }
+        SyncEvent::Renamed { taken, path } => {
+            println!("{}: {:?} is already taken, saving as {:?}", name, taken, path)
+        }
+        SyncEvent::Organized { archive, count } => {
+            println!("{}: Organized {} DATs from {:?}", name, count, archive)
+        }
+        SyncEvent::Extracted { archive, count } => {
+            println!("{}: Extracted {} files from {:?}", name, count, archive)
+        }
+        SyncEvent::Added { id, version } => println!("{}: Added {} ({})", name, id, version),
+        SyncEvent::Unchanged { id, version } => println!("{}: {} is unchanged ({})", name, id, version),
+        SyncEvent::Updated { id, from, to } => println!("{}: Updated {} ({} -> {})", name, id, from, to),
+        SyncEvent::Throttling(throttle) => println!(
+            "{}: Waiting {} seconds to avoid throttling...",
+            name,
+            throttle.as_secs()
+        ),
+        SyncEvent::Pruned(pruned) => {
+            println!("{}: Removed {} DATs the source no longer offers", name, pruned.len())
+        }
+        SyncEvent::Cataloged(count) => println!("{}: Cataloged {} DATs", name, count),
+        SyncEvent::ReportWritten(path) => println!("{}: Wrote catalog changes to {:?}", name, path),
+    }
+}
+
+async fn sync(matches: Options) -> Result<()> {
     if !matches.output_dir.exists() {
         std::fs::create_dir(&matches.output_dir)?;
     }
 
-    for source in matches.sources {
-        match source {
-            Sources::NoIntro(c) => download_nointro(c, &matches.output_dir).await?,
-            Sources::TOSEC => download_tosec(&matches.output_dir).await?,
-            Sources::Redump(c) => download_redump(c, &matches.output_dir).await?,
+    for mut source in matches.sources {
+        let name = source.name();
+        let on_event = |event| print_event(name, event);
+        let report =
+            download_source_with(source.as_mut(), &matches.output_dir, &matches.sync, &on_event)
+                .await?;
+        println!("{}: {}", name, report);
+        if !report.updated.is_empty() {
+            println!("{}: Updated {}", name, report.updated.join(", "));
+        }
+        if let Some(changes) = &report.catalog {
+            print!("{}", changes.to_text());
+        }
+    }
+
+    if matches.snapshot {
+        let now = Utc::now();
+        match take_snapshot(&matches.output_dir, now)? {
+            Some(snapshot) => {
+                println!("Saved snapshot {:?}", snapshot.path);
+                update_latest(&matches.output_dir, &snapshot)?;
+            }
+            None => println!("Nothing changed since the last snapshot"),
+        }
+        for snapshot in prune_snapshots(&matches.output_dir, &matches.retention, now)? {
+            println!("Pruned snapshot {:?}", snapshot.path);
         }
     }
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    match get_matches() {
+        Command::Sync(options) => sync(options).await,
+        Command::ListNoIntroSystems(source) => list_nointro_systems(source).await,
+        Command::Diff(options) => diff(&options),
+        Command::Convert(options) => convert(&options),
+    }
+}
@@ -1,23 +1,259 @@
 use anyhow::{anyhow, Error, Result};
-use bytes::Bytes;
+use async_trait::async_trait;
 use futures_util::TryStreamExt;
 use lazy_static::lazy_static;
 use regex::Regex;
 use reqwest::redirect::Policy;
-use reqwest::ClientBuilder;
+use reqwest::{ClientBuilder, StatusCode};
+use scraper::{ElementRef, Html, Selector};
 use serde::Serialize;
-use tokio::stream::Stream;
-use std::pin::Pin;
+use std::str::FromStr;
+use std::time::Duration;
+use crate::source::{
+    attachment_filename, Artifact, Authentication, DatSource, Download, FetchOptions, Validators,
+};
 use crate::Credentials;
 
 const HTTPS_ROOT: &str = "https://datomatic.no-intro.org/";
-const HTTPS_DAILY: &str = "https://datomatic.no-intro.org/?page=download&op=daily&s=64";
+const HTTPS_DAILY: &str = "https://datomatic.no-intro.org/?page=download&op=daily";
+const HTTPS_SYSTEMS: &str = "https://datomatic.no-intro.org/?page=download&op=dat";
+
+/// Where DAT-o-Matic serves its login form, daily pack form and single system DAT page.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    /// The site root, including the trailing slash. Logins and download locations are resolved against this.
+    pub root: String,
+    /// The daily pack download page.
+    pub daily: String,
+    /// The single system DAT download page, without a system selection.
+    pub systems: String,
+}
+
+impl Endpoints {
+    /// Derives the endpoints from a site root such as `http://localhost:8080/`.
+    pub fn with_root<S: AsRef<str>>(mut self, root: S) -> Self {
+        let root = root.as_ref();
+        self.root = if root.ends_with('/') {
+            root.to_owned()
+        } else {
+            format!("{}/", root)
+        };
+        self.daily = format!("{}?page=download&op=daily", self.root);
+        self.systems = format!("{}?page=download&op=dat", self.root);
+        self
+    }
+
+    /// The DAT download page for the system with the given ID.
+    pub fn system_url(&self, system: u32) -> String {
+        format!("{}&s={}", self.systems, system)
+    }
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            root: String::from(HTTPS_ROOT),
+            daily: String::from(HTTPS_DAILY),
+            systems: String::from(HTTPS_SYSTEMS),
+        }
+    }
+}
 
 lazy_static! {
     static ref DOWNLOAD_RE: Regex =
         Regex::new(r#"^index.php\?page=manager\&download=[0-9]+$"#).unwrap();
 }
 
+/// The kinds of DAT-o-Matic DAT atsumare can download.
+///
+/// DAT-o-Matic offers other types as well, which are not supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatType {
+    /// Standard Logiqx XML DATs.
+    Standard,
+    /// Parent/Clone XML DATs, which group clones of a game under its parent.
+    ParentClone,
+}
+
+impl DatType {
+    /// The value of the `dat_type` form field that selects this type.
+    fn form_value(&self) -> &'static str {
+        match self {
+            DatType::Standard => "standard",
+            DatType::ParentClone => "xml",
+        }
+    }
+
+    /// Distinguishes artifacts of this type from standard DATs.
+    fn suffix(&self) -> &'static str {
+        match self {
+            DatType::Standard => "",
+            DatType::ParentClone => "-parent-clone",
+        }
+    }
+
+    /// Tags the filenames of non-standard packs so they can coexist with the standard pack.
+    fn tag(&self) -> Option<&'static str> {
+        match self {
+            DatType::Standard => None,
+            DatType::ParentClone => Some("Parent-Clone"),
+        }
+    }
+}
+
+impl FromStr for DatType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "standard" => Ok(DatType::Standard),
+            "parent-clone" | "xml" => Ok(DatType::ParentClone),
+            _ => Err(anyhow!(
+                "Unsupported No-Intro DAT type {}, expected standard or parent-clone",
+                s
+            )),
+        }
+    }
+}
+
+/// The sections of DAT-o-Matic a daily pack can be built from.
+///
+/// Sections are selected with the checkboxes of the daily pack page, which are matched to the
+/// sections by their labels. See [`parse_section_checkboxes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Section {
+    /// The main No-Intro collection.
+    NoIntro,
+    /// Non-game software such as applications and demos.
+    NonGame,
+    /// Unofficial releases.
+    Unofficial,
+    /// Optical media not covered by Redump.
+    NonRedump,
+    /// Aftermarket and homebrew releases.
+    Aftermarket,
+    /// Any other section, by the label of its checkbox.
+    Other(String),
+}
+
+/// Reduces a section label to lowercase letters and digits, so that `Non-Game` matches `nongame`.
+fn normalize_label(label: &str) -> String {
+    label
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+impl Section {
+    fn key(&self) -> String {
+        normalize_label(&self.label())
+    }
+
+    /// The label of the section, which also names the collection its DATs are filed under.
+    pub fn label(&self) -> String {
+        match self {
+            Section::NoIntro => String::from("No-Intro"),
+            Section::NonGame => String::from("Non-Game"),
+            Section::Unofficial => String::from("Unofficial"),
+            Section::NonRedump => String::from("Non-Redump"),
+            Section::Aftermarket => String::from("Aftermarket"),
+            Section::Other(label) => label.clone(),
+        }
+    }
+
+    /// The section with the given label, such as `Non-Game` or the label of a system group.
+    fn from_label(label: &str) -> Section {
+        match normalize_label(label).parse() {
+            Ok(Section::Other(_)) | Err(_) => Section::Other(label.trim().to_owned()),
+            Ok(section) => section,
+        }
+    }
+
+    /// Whether the checkbox selects the section.
+    fn is_selected_by(&self, checkbox: &SectionCheckbox) -> bool {
+        normalize_label(&checkbox.label) == self.key()
+    }
+}
+
+impl FromStr for Section {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "nointro" => Ok(Section::NoIntro),
+            "nongame" => Ok(Section::NonGame),
+            "unofficial" => Ok(Section::Unofficial),
+            "nonredump" => Ok(Section::NonRedump),
+            "aftermarket" => Ok(Section::Aftermarket),
+            _ if normalize_label(s).is_empty() => Err(anyhow!("Unknown No-Intro section {:?}", s)),
+            _ => Ok(Section::Other(s.to_owned())),
+        }
+    }
+}
+
+/// A section checkbox of the daily pack form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionCheckbox {
+    /// The name of the form field.
+    pub name: String,
+    /// The value the form field is submitted with when the checkbox is checked.
+    pub value: String,
+    /// The label of the checkbox, such as `Non-Game`.
+    pub label: String,
+}
+
+/// Reads the section checkboxes of the daily pack page.
+///
+/// Checkboxes are labelled by a `label` element pointing at them, or else by the text of the
+/// element they are in.
+pub fn parse_section_checkboxes(page: &str) -> Vec<SectionCheckbox> {
+    let document = Html::parse_document(page);
+    let checkbox_selector = Selector::parse("form input[type=\"checkbox\"]").expect("selector is valid");
+    let label_selector = Selector::parse("label[for]").expect("selector is valid");
+    let text = |element: ElementRef| element.text().collect::<String>().trim().to_owned();
+
+    document
+        .select(&checkbox_selector)
+        .filter_map(|input| {
+            let name = input.value().attr("name")?.to_owned();
+            let value = input.value().attr("value").unwrap_or("on").to_owned();
+            let label = input
+                .value()
+                .id()
+                .and_then(|id| {
+                    document
+                        .select(&label_selector)
+                        .find(|label| label.value().attr("for") == Some(id))
+                })
+                .map(text)
+                .or_else(|| input.parent().and_then(ElementRef::wrap).map(text))
+                .filter(|label| !label.is_empty())?;
+            Some(SectionCheckbox { name, value, label })
+        })
+        .collect()
+}
+
+/// Inserts ` (tag)` before the extension of the filename, unless it is already tagged.
+fn tag_filename(filename: &str, tag: &str) -> String {
+    let tag = format!(" ({})", tag);
+    if filename.contains(&tag) {
+        return filename.to_owned();
+    }
+    match filename.rfind('.') {
+        Some(ext) => format!("{}{}{}", &filename[..ext], tag, &filename[ext..]),
+        None => format!("{}{}", filename, tag),
+    }
+}
+
+/// Appends ` (tag)` to the name of a collection, if there is a tag.
+fn tag_collection(collection: String, tag: Option<&str>) -> String {
+    match tag {
+        Some(tag) => format!("{} ({})", collection, tag),
+        None => collection,
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct Prepare {
     dat_type: &'static str,
@@ -26,15 +262,31 @@ pub struct Prepare {
     recaptcha_response: &'static str,
 }
 
+/// The form that prepares the DAT of a single system.
 #[derive(Debug, Serialize)]
-struct Download {
+struct SystemForm {
+    dat_type: &'static str,
+    download: &'static str,
+}
+
+impl SystemForm {
+    fn prepare(dat_type: DatType) -> Self {
+        SystemForm {
+            dat_type: dat_type.form_value(),
+            download: "Prepare",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DownloadForm {
     wtwtwtf: &'static str,
     what_im_doing_here: &'static str,
 }
 
-impl Download {
+impl DownloadForm {
     const fn download() -> Self {
-        Download {
+        DownloadForm {
             wtwtwtf: "Download",
             what_im_doing_here: ""
         }
@@ -42,15 +294,15 @@ impl Download {
 }
 
 #[derive(Debug, Serialize)]
-struct Login<'a> {
+struct LoginForm<'a> {
     username: &'a str,
     password: &'a str,
     login: &'static str,
 }
 
-impl<'a> Login<'a> {
+impl<'a> LoginForm<'a> {
     fn login(c: &'a Credentials) -> Self {
-        Login {
+        LoginForm {
             username: &c.username,
             password: &c.password,
             login: "Login",
@@ -76,14 +328,37 @@ impl Prepare {
             recaptcha_response: ""
         }
     }
+
+    /// Prepare a pack of the given DAT type instead of standard DATs.
+    pub fn with_dat_type(mut self, dat_type: DatType) -> Prepare {
+        self.dat_type = dat_type.form_value();
+        self
+    }
+
+    /// The form fields preparing the pack, along with the checked checkboxes of its sections.
+    fn form(&self, sections: &[SectionCheckbox]) -> Vec<(String, String)> {
+        let mut form = vec![
+            (String::from("dat_type"), self.dat_type.to_owned()),
+            (String::from("daily_download"), self.daily_download.to_owned()),
+        ];
+        if let Some(private) = self.private {
+            form.push((String::from("private"), private.to_owned()));
+        }
+        form.extend(sections.iter().map(|section| (section.name.clone(), section.value.clone())));
+        form.push((String::from("recaptcha_response"), self.recaptcha_response.to_owned()));
+        form
+    }
 }
 
-pub async fn fetch_authenticated_session(credentials: &Credentials) -> Result<String>{
+pub async fn fetch_authenticated_session(
+    endpoints: &Endpoints,
+    credentials: &Credentials,
+) -> Result<String> {
     let download_req = ClientBuilder::new()
         .redirect(Policy::none())
         .build()?
-        .post(HTTPS_ROOT)
-        .form(&Login::login(credentials))
+        .post(&endpoints.root)
+        .form(&LoginForm::login(credentials))
         .header("Content-Type", "application/x-www-form-urlencoded")
         .send().await?;
 
@@ -103,17 +378,124 @@ pub async fn fetch_authenticated_session(credentials: &Credentials) -> Result<St
     Ok(session)
 }
 
+/// A system DAT-o-Matic offers individual DATs for.
+#[derive(Debug, Clone)]
+pub struct System {
+    /// The ID DAT-o-Matic uses in the `s` query parameter of the system's download page.
+    pub id: u32,
+    /// The display name of the system, such as `Nintendo - Game Boy`.
+    pub name: String,
+    /// The section the system is listed under, if the systems are grouped by section.
+    pub section: Option<Section>,
+}
+
+/// Reads the systems from the single system DAT download page.
+///
+/// Systems are grouped by section with `optgroup` elements, whose labels name the sections.
+pub fn parse_systems(page: &str) -> Vec<System> {
+    let document = Html::parse_document(page);
+    let option_selector = Selector::parse("select[name=\"sel_s\"] option").expect("selector is valid");
+
+    document
+        .select(&option_selector)
+        .filter_map(|option| {
+            let id = option.value().attr("value")?.parse().ok()?;
+            let name = option.text().collect::<String>().trim().to_owned();
+            let section = option
+                .parent()
+                .and_then(ElementRef::wrap)
+                .filter(|group| group.value().name() == "optgroup")
+                .and_then(|group| group.value().attr("label"))
+                .map(Section::from_label);
+            Some(System { id, name, section })
+        })
+        .collect()
+}
+
+/// Lists the systems on the single system DAT download page.
+pub async fn fetch_systems(endpoints: &Endpoints, session: &Option<String>) -> Result<Vec<System>> {
+    let systems_page = ClientBuilder::new().build()?.get(&endpoints.systems);
+    let systems_page = if let Some(session) = session {
+        systems_page.header("Cookie", format!("PHPSESSID={}", session))
+    } else {
+        systems_page
+    }
+    .send()
+    .await?;
+
+    let systems = parse_systems(&systems_page.text().await?);
+    if systems.is_empty() {
+        return Err(anyhow!("No systems were found at {}", endpoints.systems));
+    }
+    Ok(systems)
+}
+
+/// Lists the section checkboxes of the daily pack page.
+pub async fn fetch_section_checkboxes(
+    endpoints: &Endpoints,
+    session: &Option<String>,
+) -> Result<Vec<SectionCheckbox>> {
+    let daily_page = ClientBuilder::new().build()?.get(&endpoints.daily);
+    let daily_page = if let Some(session) = session {
+        daily_page.header("Cookie", format!("PHPSESSID={}", session))
+    } else {
+        daily_page
+    }
+    .send()
+    .await?;
+
+    let checkboxes = parse_section_checkboxes(&daily_page.text().await?);
+    if checkboxes.is_empty() {
+        return Err(anyhow!("No sections were found at {}", endpoints.daily));
+    }
+    Ok(checkboxes)
+}
+
+/// Prepares the daily pack of the given sections, returning its download URL and the session to download it with.
 pub async fn fetch_download_url(
+    endpoints: &Endpoints,
+    sections: &[Section],
     prepare: &Prepare,
     session: &Option<String>,
+) -> Result<(String, String)> {
+    let checkboxes = fetch_section_checkboxes(endpoints, session).await?;
+    let selected = sections
+        .iter()
+        .map(|section| {
+            checkboxes
+                .iter()
+                .find(|checkbox| section.is_selected_by(checkbox))
+                .cloned()
+                .ok_or_else(|| anyhow!("DAT-o-Matic offers no {} section at {}", section.label(), endpoints.daily))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    fetch_prepared_url(endpoints, &endpoints.daily, &prepare.form(&selected), session).await
+}
+
+/// Prepares the DAT of a single system, returning its download URL and the session to download it with.
+pub async fn fetch_system_download_url(
+    endpoints: &Endpoints,
+    system: u32,
+    dat_type: DatType,
+    session: &Option<String>,
+) -> Result<(String, String)> {
+    let form = SystemForm::prepare(dat_type);
+    fetch_prepared_url(endpoints, &endpoints.system_url(system), &form, session).await
+}
+
+async fn fetch_prepared_url<T: Serialize>(
+    endpoints: &Endpoints,
+    page_url: &str,
+    form: &T,
+    session: &Option<String>,
 ) -> Result<(String, String)> {
     let download_req = ClientBuilder::new()
         .redirect(Policy::none())
         .build()?
-        .post(HTTPS_DAILY)
-        .form(prepare)
-        .header("Referer", HTTPS_DAILY)
-        .header("Origin", HTTPS_ROOT);
+        .post(page_url)
+        .form(form)
+        .header("Referer", page_url)
+        .header("Origin", &endpoints.root);
 
     let download_req = if let Some(session) = session {
         download_req.header("Cookie", format!("PHPSESSID={}", session))
@@ -133,26 +515,37 @@ pub async fn fetch_download_url(
         if !DOWNLOAD_RE.is_match(location) {
             Err(anyhow!("Unexpected download URL retrieved: {}", location))
         } else {
-            Ok((format!("{}{}", HTTPS_ROOT, location), session))
+            Ok((format!("{}{}", endpoints.root, location), session))
         }
     } else {
         Err(anyhow!("Unable to fetch download location."))
     }
 }
 
-pub async fn fetch_zip<S: AsRef<str>>(
+pub async fn fetch_zip<S: AsRef<str>>(download_url: S, session: S) -> Result<Download> {
+    fetch_zip_with(download_url, session, &FetchOptions::default())
+        .await?
+        .ok_or_else(|| anyhow!("Server reported the download as not modified"))
+}
+
+/// Downloads a prepared DAT or pack as described by `options`, unless it has not changed since it was saved.
+pub async fn fetch_zip_with<S: AsRef<str>>(
     download_url: S,
     session: S,
-) -> Result<(String, u64, Pin<Box<dyn Stream<Item = Result<Bytes>>>>)> {
+    options: &FetchOptions,
+) -> Result<Option<Download>> {
     let download_req = ClientBuilder::new()
         .redirect(Policy::none())
         .build()?
         .post(download_url.as_ref())
-        .form(&Download::download())
+        .form(&DownloadForm::download())
         .header("Content-Type", "application/x-www-form-urlencoded")
-        .header("Cookie", format!("PHPSESSID={}", session.as_ref()))
-        .send()
-        .await?;
+        .header("Cookie", format!("PHPSESSID={}", session.as_ref()));
+    let download_req = options.apply(download_req).send().await?;
+    if download_req.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    let offset = options.offset_of(&download_req)?;
 
     let headers = download_req.headers();
 
@@ -164,15 +557,272 @@ pub async fn fetch_zip<S: AsRef<str>>(
         return Err(anyhow!("Response was not a valid ZIP archive"));
     }
 
-    let content_diposition = headers
-        .get("content-disposition")
-        .and_then(|s| s.to_str().map(|s| s.to_string()).ok())
-        .map(|s| String::from(&s["attachment; filename=\"".len()..s.len() - 1]))
-        .ok_or(anyhow!("Unable to fetch attachment filename"))?;
+    let content_diposition = attachment_filename(headers, download_req.url())?;
+
+    Ok(Some(Download {
+        filename: content_diposition,
+        length: download_req.content_length().map(|l| offset + l).unwrap_or(0),
+        offset,
+        validators: Validators::from_headers(download_req.headers()),
+        stream: Box::pin(download_req.bytes_stream().map_err(Error::new)),
+    }))
+}
+
+/// No-Intro DAT-o-Matic daily packs and single system DATs.
+pub struct NoIntro {
+    credentials: Option<Credentials>,
+    session: Option<String>,
+    endpoints: Endpoints,
+    dat_types: Vec<DatType>,
+    packs: Vec<Vec<Section>>,
+    systems: Vec<u32>,
+}
+
+/// A daily pack to prepare and download.
+struct DailyPack {
+    id: String,
+    sections: Vec<Section>,
+    dat_type: DatType,
+    private: bool,
+}
+
+/// The DAT of a single system to prepare and download.
+struct SystemDat {
+    id: String,
+    system: u32,
+    dat_type: DatType,
+}
+
+impl NoIntro {
+    pub fn new(credentials: Option<Credentials>) -> Self {
+        NoIntro {
+            credentials,
+            session: None,
+            endpoints: Endpoints::default(),
+            dat_types: vec![DatType::Standard],
+            packs: vec![vec![Section::NoIntro]],
+            systems: Vec::new(),
+        }
+    }
+
+    /// Use the given endpoints instead of the public DAT-o-Matic instance.
+    pub fn with_endpoints(mut self, endpoints: Endpoints) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// Download daily packs of each of the given DAT types instead of only standard DATs.
+    pub fn with_dat_types(mut self, dat_types: Vec<DatType>) -> Self {
+        self.dat_types = dat_types;
+        self
+    }
+
+    /// Download one daily pack for each of the given section sets instead of only the main No-Intro section.
+    ///
+    /// The sections in a set are combined into a single pack.
+    pub fn with_sections(mut self, packs: Vec<Vec<Section>>) -> Self {
+        self.packs = packs;
+        self
+    }
+
+    /// Also download the DATs of the systems with the given IDs individually.
+    ///
+    /// To download only these systems, pass no section sets to [`NoIntro::with_sections`].
+    pub fn with_systems(mut self, systems: Vec<u32>) -> Self {
+        self.systems = systems;
+        self
+    }
+
+    /// Lists the systems DAT-o-Matic offers individual DATs for, with the session of this source.
+    pub async fn systems(&self) -> Result<Vec<System>> {
+        fetch_systems(&self.endpoints, &self.session).await
+    }
+
+    fn is_default_sections(sections: &[Section]) -> bool {
+        sections == [Section::NoIntro]
+    }
+
+    fn daily_packs(&self) -> Vec<DailyPack> {
+        let mut packs = Vec::new();
+        for sections in self.packs.iter() {
+            let sections_id = if NoIntro::is_default_sections(sections) {
+                String::new()
+            } else {
+                let keys = sections.iter().map(|s| s.key()).collect::<Vec<_>>();
+                format!("-{}", keys.join("+"))
+            };
+
+            for &dat_type in self.dat_types.iter() {
+                // DAT-o-matic requires a valid login to properly generate a private Daily DAT pack
+                let privacy: &[bool] = if self.session.is_some() {
+                    &[false, true]
+                } else {
+                    &[false]
+                };
+                for &private in privacy {
+                    let private_id = if private { "-private" } else { "" };
+                    packs.push(DailyPack {
+                        id: format!("daily{}{}{}", sections_id, dat_type.suffix(), private_id),
+                        sections: sections.clone(),
+                        dat_type,
+                        private,
+                    });
+                }
+            }
+        }
+        packs
+    }
+
+    fn system_dats(&self) -> Vec<SystemDat> {
+        let mut dats = Vec::new();
+        for &system in self.systems.iter() {
+            for &dat_type in self.dat_types.iter() {
+                dats.push(SystemDat {
+                    id: format!("system-{}{}", system, dat_type.suffix()),
+                    system,
+                    dat_type,
+                });
+            }
+        }
+        dats
+    }
+
+    async fn fetch_daily_pack(&self, pack: DailyPack, options: &FetchOptions) -> Result<Option<Download>> {
+        let prepare = if pack.private {
+            Prepare::private()
+        } else {
+            Prepare::public()
+        }
+        .with_dat_type(pack.dat_type);
+        let (download_url, session) =
+            fetch_download_url(&self.endpoints, &pack.sections, &prepare, &self.session).await?;
+
+        let mut download = match fetch_zip_with(download_url, session, options).await? {
+            Some(download) => download,
+            None => return Ok(None),
+        };
+        if !NoIntro::is_default_sections(&pack.sections) {
+            let labels = pack.sections.iter().map(|s| s.label()).collect::<Vec<_>>();
+            download.filename = tag_filename(&download.filename, &labels.join(" + "));
+        }
+        if let Some(tag) = pack.dat_type.tag() {
+            download.filename = tag_filename(&download.filename, tag);
+        }
+        Ok(Some(download))
+    }
 
-    Ok((
-        content_diposition,
-        download_req.content_length().unwrap_or(0),
-        Box::pin(download_req.bytes_stream().map_err(|e| Error::new(e))),
-    ))
+    async fn fetch_system_dat(&self, dat: SystemDat, options: &FetchOptions) -> Result<Option<Download>> {
+        let (download_url, session) =
+            fetch_system_download_url(&self.endpoints, dat.system, dat.dat_type, &self.session)
+                .await?;
+
+        let mut download = match fetch_zip_with(download_url, session, options).await? {
+            Some(download) => download,
+            None => return Ok(None),
+        };
+        if let Some(tag) = dat.dat_type.tag() {
+            download.filename = tag_filename(&download.filename, tag);
+        }
+        Ok(Some(download))
+    }
+}
+
+#[async_trait(?Send)]
+impl DatSource for NoIntro {
+    fn name(&self) -> &'static str {
+        "No-Intro"
+    }
+
+    async fn authenticate(&mut self) -> Result<Authentication> {
+        let credentials = match &self.credentials {
+            Some(credentials) => credentials,
+            None => return Ok(Authentication::Anonymous),
+        };
+
+        match fetch_authenticated_session(&self.endpoints, credentials).await.ok() {
+            Some(session) => {
+                self.session = Some(session);
+                Ok(Authentication::LoggedIn(credentials.username.clone()))
+            }
+            None => Ok(Authentication::InvalidCredentials),
+        }
+    }
+
+    async fn artifacts(&self) -> Result<Vec<Artifact>> {
+        let daily_packs = self.daily_packs().into_iter().map(|pack| {
+            let labels = pack.sections.iter().map(|s| s.label()).collect::<Vec<_>>();
+            let mut collection = tag_collection(labels.join(" + "), pack.dat_type.tag());
+            if pack.private {
+                collection = tag_collection(collection, Some("Private"));
+            }
+            Artifact {
+                url: self.endpoints.daily.clone(),
+                id: pack.id,
+                private: pack.private,
+                version: None,
+                extract: None,
+                dir: None,
+                collection: Some(collection),
+            }
+        });
+        let systems = if self.systems.is_empty() {
+            Vec::new()
+        } else {
+            self.systems().await?
+        };
+        let system_dats = self
+            .system_dats()
+            .into_iter()
+            .map(|dat| {
+                // System DATs are filed with the daily pack of the section the system is listed under
+                let section = systems
+                    .iter()
+                    .find(|system| system.id == dat.system)
+                    .ok_or_else(|| anyhow!("DAT-o-Matic offers no system with ID {}", dat.system))?
+                    .section
+                    .clone()
+                    .unwrap_or(Section::NoIntro);
+                Ok(Artifact {
+                    url: self.endpoints.system_url(dat.system),
+                    id: dat.id,
+                    private: false,
+                    version: None,
+                    extract: None,
+                    dir: None,
+                    collection: Some(tag_collection(section.label(), dat.dat_type.tag())),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(daily_packs.chain(system_dats).collect())
+    }
+
+    async fn fetch(&self, artifact: &Artifact) -> Result<Download> {
+        self.fetch_with(artifact, &FetchOptions::default())
+            .await?
+            .ok_or_else(|| anyhow!("Server reported the download as not modified"))
+    }
+
+    async fn fetch_with(
+        &self,
+        artifact: &Artifact,
+        options: &FetchOptions,
+    ) -> Result<Option<Download>> {
+        if let Some(pack) = self
+            .daily_packs()
+            .into_iter()
+            .find(|pack| pack.id == artifact.id)
+        {
+            return self.fetch_daily_pack(pack, options).await;
+        }
+        let dat = self
+            .system_dats()
+            .into_iter()
+            .find(|dat| dat.id == artifact.id)
+            .ok_or_else(|| anyhow!("Unknown No-Intro artifact {}", artifact.id))?;
+        self.fetch_system_dat(dat, options).await
+    }
+
+    fn throttle(&self) -> Option<Duration> {
+        Some(Duration::new(30, 0))
+    }
 }
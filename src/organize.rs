@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+use crate::extract::write_atomically;
+use crate::snapshot::list_files;
+use crate::state::is_dat_name;
+
+lazy_static! {
+    /// Trailing tags that version a DAT rather than name its system, such as `(20200101-000000)`,
+    /// `(1)`, `(2020-01-01 00-00-00)` or `(TOSEC-v2020-07-29)`.
+    static ref VERSION_TAG_RE: Regex = Regex::new(
+        r#"\s*\((?:[0-9]+|[0-9]{8}-[0-9]{6}|[0-9]{4}-[0-9]{2}-[0-9]{2}[^)]*|TOSEC-v[^)]*)\)$"#
+    )
+    .unwrap();
+    /// The names the organizer gives DATs whose place was taken, such as `Nintendo - Game Boy (2).dat`.
+    static ref NUMBERED_RE: Regex = Regex::new(r#"^(.+) \([0-9]+\)\.dat$"#).unwrap();
+}
+
+/// The system a DAT is for, derived from its filename without extension or version tags.
+///
+/// For example, `Sony - PlayStation - Datfile (1) (2020-01-01 00-00-00).dat` is for `Sony - PlayStation`.
+pub fn system_name(filename: &str) -> String {
+    let stem = Path::new(filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| filename.to_owned());
+    let mut name = stem.as_str();
+    while let Some(tag) = VERSION_TAG_RE.find(name) {
+        name = &name[..tag.start()];
+    }
+    name.trim_end_matches(" - Datfile").trim().to_owned()
+}
+
+/// The name of the DAT that took the place of the organized DAT `filename`, if the organizer
+/// numbered it for that reason.
+///
+/// For example, `Nintendo - Game Boy (2).dat` was numbered if `Nintendo - Game Boy.dat` was placed before it.
+pub(crate) fn unnumbered_name(filename: &str) -> Option<String> {
+    NUMBERED_RE
+        .captures(filename)
+        .map(|captures| format!("{}.dat", &captures[1]))
+}
+
+/// Places DATs into a tree of `<collection>/<system>.dat` under a root directory.
+///
+/// Every DAT placed during the lifetime of an organizer gets its own path. If two DATs
+/// would share a path, the later one is numbered, such as `Nintendo - Game Boy (2).dat`.
+#[derive(Debug)]
+pub struct Organizer {
+    root: PathBuf,
+    claimed: HashSet<PathBuf>,
+    renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Organizer {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Organizer {
+            root: root.into(),
+            claimed: HashSet::new(),
+            renamed: Vec::new(),
+        }
+    }
+
+    /// Claims the path of a DAT placed earlier, so that no other DAT is placed there.
+    pub fn reserve<P: Into<PathBuf>>(&mut self, path: P) {
+        self.claimed.insert(path.into());
+    }
+
+    /// The DATs numbered since this was last called, as pairs of the path that was taken and the path
+    /// the DAT was placed at instead.
+    pub fn take_renamed(&mut self) -> Vec<(PathBuf, PathBuf)> {
+        std::mem::take(&mut self.renamed)
+    }
+
+    /// Picks an unclaimed path for the DAT of `system` in `collection`.
+    fn claim(&mut self, collection: &Path, system: &str) -> PathBuf {
+        let dir = self.root.join(collection);
+        let mut path = dir.join(format!("{}.dat", system));
+        let mut n = 2;
+        while self.claimed.contains(&path) {
+            path = dir.join(format!("{} ({}).dat", system, n));
+            n += 1;
+        }
+        if n > 2 {
+            self.renamed.push((dir.join(format!("{}.dat", system)), path.clone()));
+        }
+        self.claimed.insert(path.clone());
+        path
+    }
+
+    /// Places every DAT in the ZIP archive at `archive` that matches `filter`.
+    ///
+    /// DATs in a directory of the archive are placed in a collection named after that directory,
+    /// and the others in `collection`. Returns the paths of the placed DATs.
+    pub fn organize_zip<P, F>(&mut self, archive: P, collection: &str, filter: F) -> Result<Vec<PathBuf>>
+    where
+        P: AsRef<Path>,
+        F: Fn(&str) -> bool,
+    {
+        let mut zip = ZipArchive::new(File::open(archive.as_ref())?)?;
+        let mut organized = Vec::new();
+
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+            if !file.is_file() || !is_dat_name(file.name()) || !filter(file.name()) {
+                continue;
+            }
+
+            let name = file
+                .enclosed_name()
+                .ok_or_else(|| anyhow!("Archive contains an unsafe path: {}", file.name()))?
+                .to_owned();
+            let member_collection = name
+                .parent()
+                .filter(|parent| parent != &Path::new(""))
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| PathBuf::from(collection));
+            let system = system_name(&name.file_name().unwrap_or_default().to_string_lossy());
+            let output_path = self.claim(&member_collection, &system);
+            if let Some(parent) = output_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_atomically(&mut file, &output_path)?;
+            organized.push(output_path);
+        }
+
+        Ok(organized)
+    }
+
+    /// Places the loose DAT at `dat` in `collection`. Returns the path of the placed DAT.
+    pub fn organize_dat<P: AsRef<Path>>(&mut self, dat: P, collection: &str) -> Result<PathBuf> {
+        let filename = dat
+            .as_ref()
+            .file_name()
+            .ok_or_else(|| anyhow!("{:?} is not a file", dat.as_ref()))?
+            .to_string_lossy()
+            .into_owned();
+        let output_path = self.claim(Path::new(collection), &system_name(&filename));
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomically(&mut File::open(dat.as_ref())?, &output_path)?;
+        Ok(output_path)
+    }
+
+    /// Removes the DATs under the root that were neither placed nor reserved by the organizer,
+    /// such as those of systems a source no longer offers. Returns the paths of the removed DATs.
+    pub fn prune(&self) -> Result<Vec<PathBuf>> {
+        if !self.root.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut pruned = Vec::new();
+        for file in list_files(&self.root, &|_| true)? {
+            if is_dat_name(&file.to_string_lossy()) && !self.claimed.contains(&file) {
+                fs::remove_file(&file)?;
+                pruned.push(file);
+            }
+        }
+        Ok(pruned)
+    }
+}
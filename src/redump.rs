@@ -1,25 +1,66 @@
 use crate::Credentials;
 use crate::convert::convert_to_xml_dat;
+use crate::source::{
+    attachment_filename, fetch_cached_page, Artifact, Authentication, CachedPage, DatSource,
+    Download, FetchOptions, LoginRequired, PageCache, Validators,
+};
 
 use anyhow::{anyhow, Error, Result};
-use bytes::Bytes;
+use async_trait::async_trait;
 use futures_util::TryStreamExt;
 use futures_util::stream;
 use lazy_static::lazy_static;
 use regex::Regex;
 use reqwest::redirect::Policy;
-use reqwest::ClientBuilder;
+use reqwest::{ClientBuilder, StatusCode};
 use scraper::{Html, Selector};
 use serde::Serialize;
-use std::pin::Pin;
-use tokio::stream::Stream;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::str::FromStr;
 
 const HTTP_LOGIN: &str = "http://forum.redump.org/login/";
 const HTTP_DOWNLOADS: &str = "http://redump.org/downloads/";
 const HTTP_ROOT: &str = "http://redump.org";
 
+/// Where redump.org lists its downloads table and serves the forum login for private DATs.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    /// The site root, without a trailing slash. Download links are resolved against this.
+    pub root: String,
+    /// The downloads page listing every DAT.
+    pub downloads: String,
+    /// The forum login page.
+    pub login: String,
+}
+
+impl Endpoints {
+    /// Derives the site endpoints from a site root such as `http://localhost:8080`.
+    pub fn with_root<S: AsRef<str>>(mut self, root: S) -> Self {
+        self.root = root.as_ref().trim_end_matches('/').to_owned();
+        self.downloads = format!("{}/downloads/", self.root);
+        self
+    }
+
+    /// Derives the login endpoint from a forum root such as `http://localhost:8080`.
+    pub fn with_forum<S: AsRef<str>>(mut self, forum: S) -> Self {
+        self.login = format!("{}/login/", forum.as_ref().trim_end_matches('/'));
+        self
+    }
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            root: String::from(HTTP_ROOT),
+            downloads: String::from(HTTP_DOWNLOADS),
+            login: String::from(HTTP_LOGIN),
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
-struct Login<'a> {
+struct LoginForm<'a> {
     req_username: &'a str,
     req_password: &'a str,
     login: &'static str,
@@ -28,12 +69,12 @@ struct Login<'a> {
     csrf_token: &'a str,
 }
 
-impl<'a> Login<'a> {
-    fn login(c: &'a Credentials, csrf: &'a String) -> Self {
-        Login {
+impl<'a> LoginForm<'a> {
+    fn login(c: &'a Credentials, csrf: &'a str) -> Self {
+        LoginForm {
             req_username: &c.username,
             req_password: &c.password,
-            csrf_token: csrf.as_ref(),
+            csrf_token: csrf,
             login: "Login",
             form_sent: "1",
             redirect_url: "http://forum.redump.org/",
@@ -46,11 +87,14 @@ lazy_static! {
         Regex::new(r#"<input type="hidden" name="csrf_token" value="([\w]+?)" />"#).unwrap();
 }
 
-pub async fn fetch_authenticated_session(credentials: &Credentials) -> Result<String> {
+pub async fn fetch_authenticated_session(
+    endpoints: &Endpoints,
+    credentials: &Credentials,
+) -> Result<String> {
     let login_page = ClientBuilder::new()
         .redirect(Policy::none())
         .build()?
-        .get(HTTP_LOGIN)
+        .get(&endpoints.login)
         .send()
         .await?;
 
@@ -70,8 +114,8 @@ pub async fn fetch_authenticated_session(credentials: &Credentials) -> Result<St
     let login_req = ClientBuilder::new()
         .redirect(Policy::none())
         .build()?
-        .post(HTTP_LOGIN)
-        .form(&Login::login(&credentials, &csrf))
+        .post(&endpoints.login)
+        .form(&LoginForm::login(credentials, &csrf))
         .header("Cookie", format!("PHPSESSID={}", session_id))
         .header("Content-Type", "application/x-www-form-urlencoded")
         .send()
@@ -91,73 +135,449 @@ pub async fn fetch_authenticated_session(credentials: &Credentials) -> Result<St
     Ok(session)
 }
 
-pub async fn fetch_download_urls<S: AsRef<str>>(session: &Option<S>) -> Result<Vec<String>> {
-    let downloads_page = ClientBuilder::new().build()?.get(HTTP_DOWNLOADS);
+/// A system listed on the Redump downloads page.
+#[derive(Debug, Clone)]
+pub struct System {
+    /// The display name of the system, such as `Sony - PlayStation`.
+    pub name: String,
+    /// The short name redump.org uses in download links, such as `psx`.
+    pub slug: String,
+    /// The download links offered for the system, such as `/datfile/psx/`.
+    pub links: Vec<String>,
+}
+
+/// Selects which systems to download from Redump.
+///
+/// A system is selected if it matches any include rule (or no include rules are given),
+/// and does not match any exclude rule.
+#[derive(Debug, Clone, Default)]
+pub struct SystemFilter {
+    /// Slugs of the systems to include.
+    pub include: Vec<String>,
+    /// Slugs of the systems to exclude.
+    pub exclude: Vec<String>,
+    /// Patterns matching the names of systems to include.
+    pub include_names: Vec<Regex>,
+    /// Patterns matching the names of systems to exclude.
+    pub exclude_names: Vec<Regex>,
+}
+
+impl SystemFilter {
+    pub fn matches(&self, system: &System) -> bool {
+        let included = (self.include.is_empty() && self.include_names.is_empty())
+            || self.include.iter().any(|slug| slug == &system.slug)
+            || self.include_names.iter().any(|re| re.is_match(&system.name));
+        let excluded = self.exclude.iter().any(|slug| slug == &system.slug)
+            || self.exclude_names.iter().any(|re| re.is_match(&system.name));
+        included && !excluded
+    }
+}
+
+/// The flavours of DAT redump.org can generate for a system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DatVariant {
+    /// The plain DAT without any extra fields.
+    Plain,
+    /// Includes the serial of each disc.
+    Serial,
+    /// Includes the version of each disc.
+    Version,
+    /// Includes both the serial and the version of each disc.
+    #[default]
+    SerialVersion,
+}
+
+impl DatVariant {
+    /// The path suffix that selects the variant in a `/datfile/` link.
+    fn suffix(&self) -> &'static str {
+        match self {
+            DatVariant::Plain => "",
+            DatVariant::Serial => "/serial",
+            DatVariant::Version => "/version",
+            DatVariant::SerialVersion => "/serial,version",
+        }
+    }
+}
+
+impl FromStr for DatVariant {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "plain" => Ok(DatVariant::Plain),
+            "serial" => Ok(DatVariant::Serial),
+            "version" => Ok(DatVariant::Version),
+            "serial,version" => Ok(DatVariant::SerialVersion),
+            _ => Err(anyhow!("Unknown Redump DAT variant {}", s)),
+        }
+    }
+}
+
+/// The kinds of packs offered on the Redump downloads page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackKind {
+    /// DAT files.
+    Datfile,
+    /// Cuesheets for every disc.
+    Cuesheets,
+    /// LibCrypt subchannel data (SBI files).
+    Sbi,
+    /// GD-ROM track layouts (GDI files).
+    Gdi,
+    /// Disc decryption keys.
+    Keys,
+}
+
+impl PackKind {
+    /// The first path segment of download links for the kind, such as `cues` in `/cues/psx/`.
+    pub fn path(&self) -> &'static str {
+        match self {
+            PackKind::Datfile => "datfile",
+            PackKind::Cuesheets => "cues",
+            PackKind::Sbi => "sbi",
+            PackKind::Gdi => "gdi",
+            PackKind::Keys => "keys",
+        }
+    }
+
+    fn from_link(link: &str) -> Option<Self> {
+        match link.split('/').nth(1)? {
+            "datfile" => Some(PackKind::Datfile),
+            "cues" => Some(PackKind::Cuesheets),
+            "sbi" => Some(PackKind::Sbi),
+            "gdi" => Some(PackKind::Gdi),
+            "keys" => Some(PackKind::Keys),
+            _ => None,
+        }
+    }
+
+    /// Whether redump.org is known to serve every pack of the kind only to logged in users.
+    ///
+    /// Packs of other kinds that redump.org turns out to only serve to logged in users, such as
+    /// those of systems whose DATs are private, are skipped when downloaded without logging in.
+    pub fn requires_login(&self) -> bool {
+        matches!(self, PackKind::Keys)
+    }
+
+    /// The collection the packs of this kind are organized under, named like redump.org names
+    /// the packs, such as `Cuesheets`.
+    pub fn collection(&self) -> &'static str {
+        match self {
+            PackKind::Datfile => "Redump",
+            PackKind::Cuesheets => "Cuesheets",
+            PackKind::Sbi => "SBI Subchannels",
+            PackKind::Gdi => "GDI Files",
+            PackKind::Keys => "Keys",
+        }
+    }
+
+    /// The subdirectory packs of this kind are saved under. DAT files are saved to the output directory.
+    fn dir(&self) -> Option<&'static str> {
+        match self {
+            PackKind::Datfile => None,
+            kind => Some(kind.path()),
+        }
+    }
+}
+
+impl FromStr for PackKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        PackKind::from_link(&format!("/{}/", s))
+            .ok_or_else(|| anyhow!("Unknown Redump pack kind {}", s))
+    }
+}
+
+/// Which DAT variant to request for each system.
+#[derive(Debug, Clone, Default)]
+pub struct Variants {
+    /// The variant to request for systems without an override.
+    pub default: DatVariant,
+    /// Overrides keyed by system slug.
+    pub systems: HashMap<String, DatVariant>,
+}
+
+impl Variants {
+    pub fn for_system(&self, slug: &str) -> DatVariant {
+        self.systems.get(slug).copied().unwrap_or(self.default)
+    }
+}
+
+fn datfile_url(endpoints: &Endpoints, link: &str, variant: DatVariant) -> String {
+    match variant {
+        DatVariant::Plain => format!("{}{}", endpoints.root, link),
+        _ => format!("{}{}{}", endpoints.root, link.trim_end_matches('/'), variant.suffix()),
+    }
+}
+
+/// Fetches the downloads page, conditionally on it having changed since `cached` was saved.
+pub async fn fetch_systems_page<S: AsRef<str>>(
+    endpoints: &Endpoints,
+    session: &Option<S>,
+    cached: Option<CachedPage>,
+) -> Result<CachedPage> {
+    let downloads_page = ClientBuilder::new().build()?.get(&endpoints.downloads);
     let downloads_page = if let Some(session) = session {
         downloads_page.header("Cookie", format!("redump_cookie={}", session.as_ref()))
     } else {
         downloads_page
-    }
-    .send()
-    .await?;
+    };
+    fetch_cached_page(downloads_page, cached).await
+}
+
+/// Lists the systems on the downloads page along with their download links.
+pub async fn fetch_systems<S: AsRef<str>>(
+    endpoints: &Endpoints,
+    session: &Option<S>,
+) -> Result<Vec<System>> {
+    let page = fetch_systems_page(endpoints, session, None).await?;
+    parse_systems(&page.body)
+}
 
-    let page_body = &downloads_page.text().await?;
+/// Reads the systems and their download links from the downloads page.
+pub fn parse_systems(page_body: &str) -> Result<Vec<System>> {
     let fragment = Html::parse_document(page_body);
-    let selector = Selector::parse("table.statistics > tbody > tr > td > a")
+    let row_selector = Selector::parse("table.statistics > tbody > tr")
+        .map_err(|_| anyhow!("Unable to parse selector! This should never happen!!"))?;
+    let cell_selector = Selector::parse("td")
+        .map_err(|_| anyhow!("Unable to parse selector! This should never happen!!"))?;
+    let anchor_selector = Selector::parse("td > a")
         .map_err(|_| anyhow!("Unable to parse selector! This should never happen!!"))?;
 
-    let anchors = fragment
-        .select(&selector)
-        .map(|n| n.value())
-        .map(|n| n.attr("href"))
-        .flat_map(|n| n)
-        .filter(|n| n.starts_with("/datfile/"))
-        // get the serial,version DAT
-        .map(|n| format!("{}{}/serial,version", HTTP_ROOT, n))
+    let systems = fragment
+        .select(&row_selector)
+        .filter_map(|row| {
+            let links = row
+                .select(&anchor_selector)
+                .filter_map(|n| n.value().attr("href"))
+                .map(|n| n.to_owned())
+                .collect::<Vec<_>>();
+            // links are of the form /datfile/psx/
+            let slug = links.first()?.split('/').nth(2)?.to_owned();
+            let name = row
+                .select(&cell_selector)
+                .next()
+                .map(|cell| cell.text().collect::<String>().trim().to_owned())
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| slug.clone());
+            Some(System { name, slug, links })
+        })
+        .collect::<Vec<_>>();
+    Ok(systems)
+}
+
+pub async fn fetch_download_urls<S: AsRef<str>>(
+    endpoints: &Endpoints,
+    session: &Option<S>,
+    filter: &SystemFilter,
+    variants: &Variants,
+) -> Result<Vec<String>> {
+    let anchors = fetch_systems(endpoints, session)
+        .await?
+        .into_iter()
+        .filter(|system| filter.matches(system))
+        .flat_map(|system| {
+            let variant = variants.for_system(&system.slug);
+            system
+                .links
+                .into_iter()
+                .filter(|n| n.starts_with("/datfile/"))
+                .map(move |n| datfile_url(endpoints, &n, variant))
+        })
         .collect::<Vec<_>>();
     Ok(anchors)
 }
 
-pub async fn fetch_zip<S: AsRef<str>>(
+pub async fn fetch_zip<S: AsRef<str>>(download_url: S, session: &Option<S>) -> Result<Download> {
+    fetch_zip_with(download_url, session, &FetchOptions::default())
+        .await?
+        .ok_or_else(|| anyhow!("Server reported the download as not modified"))
+}
+
+/// Starts downloading the DAT or pack as described by `options`, unless it has not changed since it was saved.
+pub async fn fetch_zip_with<S: AsRef<str>>(
     download_url: S,
     session: &Option<S>,
-) -> Result<(String, u64, Pin<Box<dyn Stream<Item = Result<Bytes>>>>)> {
+    options: &FetchOptions,
+) -> Result<Option<Download>> {
     let download_req = ClientBuilder::new().build()?.get(download_url.as_ref());
     let download_req = if let Some(session) = session {
         download_req.header("Cookie", format!("redump_cookie={}", session.as_ref()))
     } else {
         download_req
+    };
+    let download_req = options.apply(download_req).send().await?;
+    if download_req.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
     }
-    .send()
-    .await?;
+    let offset = options.offset_of(&download_req)?;
 
     let headers = download_req.headers();
-    let content_diposition = headers
-        .get("content-disposition")
-        .and_then(|s| s.to_str().map(|s| s.to_string()).ok())
-        .map(|s| String::from(&s["attachment; filename=\"".len()..s.len() - 1]))
-        .ok_or(anyhow!("Unable to fetch attachment filename"))?;
+    let content_diposition = attachment_filename(headers, download_req.url())?;
 
     match headers.get("content-type").and_then(|f| f.to_str().ok()) {
         Some("application/x-zip") | Some("application/zip") => {
-            Ok((
-                content_diposition,
-                download_req.content_length().unwrap_or(0),
-                Box::pin(download_req.bytes_stream().map_err(|e| Error::new(e))),
-            ))
+            Ok(Some(Download {
+                filename: content_diposition,
+                length: download_req.content_length().map(|l| offset + l).unwrap_or(0),
+                offset,
+                validators: Validators::from_headers(download_req.headers()),
+                stream: Box::pin(download_req.bytes_stream().map_err(Error::new)),
+            }))
         },
         Some("application/x-ms-download; charset=ISO-8859-1") => {
+            // Text DATs are converted as a whole, so the converted bytes on disk say nothing about
+            // how much of the original was downloaded.
+            if offset != 0 {
+                return Err(anyhow!("Unable to resume a ClrMamePro DAT download"));
+            }
+            let validators = Validators::from_headers(download_req.headers());
             // ISO-8859-1 is the same as windows-1252
             let content = download_req.text_with_charset("windows-1252").await?;
+            // The conversion keeps every field, including the serials and versions of the requested variant.
             let bytes = convert_to_xml_dat(&content, "redump.org", Some(bios_normalizing))?;
             
-            Ok((content_diposition, bytes.len() as u64, Box::pin(stream::iter(vec![Ok(bytes)].into_iter()))))
+            Ok(Some(Download {
+                filename: content_diposition,
+                length: bytes.len() as u64,
+                offset: 0,
+                validators,
+                stream: Box::pin(stream::iter(vec![Ok(bytes)])),
+            }))
         },
+        // redump.org answers anonymous requests for login-only packs with its login page.
+        Some(i) if i.starts_with("text/html") && session.is_none() => {
+            Err(LoginRequired(download_url.as_ref().to_owned()).into())
+        }
         Some(i) => Err(anyhow!("Response was not a valid ZIP archive or DAT file: {}", i)),
         None => Err(anyhow!("Response did not give valid content-type"))
     }
 }
 
+/// Redump DAT files, one per system.
+pub struct Redump {
+    credentials: Option<Credentials>,
+    session: Option<String>,
+    endpoints: Endpoints,
+    filter: SystemFilter,
+    variants: Variants,
+    kinds: Vec<PackKind>,
+    pages: RefCell<PageCache>,
+}
+
+impl Redump {
+    pub fn new(credentials: Option<Credentials>) -> Self {
+        Redump {
+            credentials,
+            session: None,
+            endpoints: Endpoints::default(),
+            filter: SystemFilter::default(),
+            variants: Variants::default(),
+            kinds: vec![PackKind::Datfile],
+            pages: RefCell::new(PageCache::new()),
+        }
+    }
+
+    /// Only download DATs for the systems selected by the filter.
+    pub fn with_filter(mut self, filter: SystemFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Request the given DAT variants instead of the serial and version DAT for every system.
+    pub fn with_variants(mut self, variants: Variants) -> Self {
+        self.variants = variants;
+        self
+    }
+
+    /// Download the given kinds of packs instead of only DAT files.
+    pub fn with_kinds(mut self, kinds: Vec<PackKind>) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    /// Use the given endpoints instead of redump.org.
+    pub fn with_endpoints(mut self, endpoints: Endpoints) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl DatSource for Redump {
+    fn name(&self) -> &'static str {
+        "Redump"
+    }
+
+    async fn authenticate(&mut self) -> Result<Authentication> {
+        let credentials = match &self.credentials {
+            Some(credentials) => credentials,
+            None => return Ok(Authentication::Anonymous),
+        };
+
+        match fetch_authenticated_session(&self.endpoints, credentials).await.ok() {
+            Some(session) => {
+                self.session = Some(session);
+                Ok(Authentication::LoggedIn(credentials.username.clone()))
+            }
+            None => Ok(Authentication::InvalidCredentials),
+        }
+    }
+
+    async fn artifacts(&self) -> Result<Vec<Artifact>> {
+        let cached = self.pages.borrow().get(&self.endpoints.downloads).cloned();
+        let page = fetch_systems_page(&self.endpoints, &self.session, cached).await?;
+        let systems = parse_systems(&page.body)?;
+        self.pages
+            .borrow_mut()
+            .insert(self.endpoints.downloads.clone(), page);
+        Ok(systems
+            .into_iter()
+            .filter(|system| self.filter.matches(system))
+            .flat_map(|system| {
+                let variant = self.variants.for_system(&system.slug);
+                system.links.into_iter().filter_map(move |n| {
+                    let kind = PackKind::from_link(&n).filter(|k| self.kinds.contains(k))?;
+                    let url = match kind {
+                        PackKind::Datfile => datfile_url(&self.endpoints, &n, variant),
+                        _ => format!("{}{}", self.endpoints.root, n),
+                    };
+                    Some(Artifact {
+                        id: n.trim_matches('/').to_owned(),
+                        url,
+                        private: kind.requires_login(),
+                        version: None,
+                        extract: None,
+                        dir: kind.dir().map(String::from),
+                        collection: Some(kind.collection().to_owned()),
+                    })
+                })
+            })
+            .collect())
+    }
+
+    async fn fetch(&self, artifact: &Artifact) -> Result<Download> {
+        fetch_zip(&artifact.url, &self.session.as_ref()).await
+    }
+
+    async fn fetch_with(
+        &self,
+        artifact: &Artifact,
+        options: &FetchOptions,
+    ) -> Result<Option<Download>> {
+        fetch_zip_with(&artifact.url, &self.session.as_ref(), options).await
+    }
+
+    fn restore_pages(&mut self, pages: PageCache) {
+        self.pages = RefCell::new(pages);
+    }
+
+    fn saved_pages(&self) -> PageCache {
+        self.pages.borrow().clone()
+    }
+}
+
 // really hacky normalization function.
 fn bios_normalizing(orig: &str) -> String {
     let mut working = orig.replace("Kernel ", ""); // replace "Kernel Version" with just "Kernel"
@@ -182,6 +602,5 @@ fn bios_normalizing(orig: &str) -> String {
     }
     
     let region_str = region_str.into_iter().collect::<Vec<_>>().join(", ");
-    let working = working.replacen(" (", &format!(" ({}) (", region_str), 1);
-    working
+    working.replacen(" (", &format!(" ({}) (", region_str), 1)
 }
\ No newline at end of file
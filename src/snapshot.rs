@@ -0,0 +1,286 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::download::REPORTS_DIR;
+use crate::organize::{system_name, unnumbered_name};
+use crate::state::{relative_path, sha256_file};
+
+/// The directory within the output directory that snapshots are stored in.
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// The name of the view of the newest DAT of each system within the output directory.
+pub const LATEST_DIR: &str = "latest";
+
+/// The format of snapshot directory names, which sort in the order they were taken.
+const SNAPSHOT_FORMAT: &str = "%Y-%m-%dT%H%M%SZ";
+
+/// A dated copy of the organized DATs of the output directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// When the snapshot was taken.
+    pub taken_at: DateTime<Utc>,
+    /// The directory of the snapshot.
+    pub path: PathBuf,
+}
+
+/// Which snapshots to keep when pruning.
+///
+/// A snapshot is kept if it is one of the newest `keep_last` snapshots, or the newest snapshot of one
+/// of the `keep_monthly` most recent months. Snapshots older than `max_age` are pruned regardless.
+/// The newest snapshot is never pruned.
+#[derive(Debug, Clone, Default)]
+pub struct Retention {
+    /// The number of most recent snapshots to keep.
+    pub keep_last: Option<usize>,
+    /// The number of most recent months to keep a snapshot of.
+    pub keep_monthly: Option<usize>,
+    /// The age after which snapshots are pruned.
+    pub max_age: Option<Duration>,
+}
+
+impl Retention {
+    /// Picks the snapshots to prune out of `snapshots`, which must be sorted oldest first.
+    pub fn expired<'a>(&self, snapshots: &'a [Snapshot], now: DateTime<Utc>) -> Vec<&'a Snapshot> {
+        let mut kept = HashSet::new();
+        if let Some(keep_last) = self.keep_last {
+            kept.extend(snapshots.iter().rev().take(keep_last).map(|s| &s.path));
+        }
+        if let Some(keep_monthly) = self.keep_monthly {
+            let mut months = HashSet::new();
+            for snapshot in snapshots.iter().rev() {
+                if months.len() == keep_monthly {
+                    break;
+                }
+                if months.insert((snapshot.taken_at.year(), snapshot.taken_at.month())) {
+                    kept.insert(&snapshot.path);
+                }
+            }
+        }
+        let counts = self.keep_last.is_some() || self.keep_monthly.is_some();
+
+        let newest = snapshots.last().map(|s| &s.path);
+        snapshots
+            .iter()
+            .filter(|s| Some(&s.path) != newest)
+            .filter(|s| {
+                (counts && !kept.contains(&s.path))
+                    || self.max_age.map(|age| now - s.taken_at > age).unwrap_or(false)
+            })
+            .collect()
+    }
+}
+
+/// Whether the file or directory at `path` in the output directory `dir` belongs in a snapshot.
+///
+/// Snapshots hold the organized DATs in the source directories, such as `redump/Redump/Sony - PlayStation.dat`,
+/// but not the downloaded archives, reports or atsumare's own files.
+fn is_snapshotted(dir: &Path, path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if path.parent() == Some(dir) {
+        let latest_tmp = format!("{}.tmp", LATEST_DIR);
+        let reserved = [SNAPSHOTS_DIR, LATEST_DIR, &latest_tmp, REPORTS_DIR].contains(&name.as_ref());
+        return path.is_dir() && !reserved;
+    }
+    path.is_dir() || name.ends_with(".dat")
+}
+
+/// Lists the files below `root`, skipping files and directories that `filter` rejects.
+pub(crate) fn list_files(root: &Path, filter: &dyn Fn(&Path) -> bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if !filter(&path) {
+                continue;
+            }
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Copies the file at `from` to `to`, keeping its modification time.
+fn copy_file(from: &Path, to: &Path) -> Result<()> {
+    fs::copy(from, to)?;
+    let modified = fs::metadata(from)?.modified()?;
+    fs::OpenOptions::new().write(true).open(to)?.set_modified(modified)?;
+    Ok(())
+}
+
+/// Whether the files at `a` and `b` have the same contents.
+fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a_meta), Ok(b_meta)) if a_meta.len() == b_meta.len() => {
+            Ok(sha256_file(a)? == sha256_file(b)?)
+        }
+        (_, Err(e)) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        (Ok(_), Ok(_)) => Ok(false),
+        (Err(e), _) | (_, Err(e)) => Err(e.into()),
+    }
+}
+
+/// Lists the snapshots of the output directory `dir`, oldest first.
+pub fn list_snapshots<P: AsRef<Path>>(dir: P) -> Result<Vec<Snapshot>> {
+    let snapshots_dir = dir.as_ref().join(SNAPSHOTS_DIR);
+    let entries = match fs::read_dir(&snapshots_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut snapshots = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Anything that is not named like a snapshot, such as an unfinished one, is left alone.
+        if let Ok(taken_at) = Utc.datetime_from_str(&name, SNAPSHOT_FORMAT) {
+            snapshots.push(Snapshot {
+                taken_at,
+                path: entry.path(),
+            });
+        }
+    }
+    snapshots.sort_by_key(|s| s.taken_at);
+    Ok(snapshots)
+}
+
+/// Takes a snapshot of the organized DATs of the output directory `dir`, dated `now`.
+///
+/// Files that are unchanged since the previous snapshot are hardlinked to it, so that only new and
+/// updated files take up space. Returns `None` without taking a snapshot if nothing changed.
+pub fn take_snapshot<P: AsRef<Path>>(dir: P, now: DateTime<Utc>) -> Result<Option<Snapshot>> {
+    let dir = dir.as_ref();
+    let files = list_files(dir, &|path| is_snapshotted(dir, path))?;
+    let previous = list_snapshots(dir)?.pop();
+    let previous_files = match &previous {
+        Some(previous) => list_files(&previous.path, &|_| true)?,
+        None => Vec::new(),
+    };
+
+    let name = now.format(SNAPSHOT_FORMAT).to_string();
+    let path = dir.join(SNAPSHOTS_DIR).join(&name);
+    if path.exists() {
+        return Err(anyhow!("Snapshot {} already exists", name));
+    }
+    let temp_path = dir.join(SNAPSHOTS_DIR).join(format!("{}.tmp", name));
+    if temp_path.exists() {
+        fs::remove_dir_all(&temp_path)?;
+    }
+
+    let mut changed = previous.is_none() || files.len() != previous_files.len();
+    for file in &files {
+        let relative = relative_path(dir, file);
+        let output_path = temp_path.join(&relative);
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let previous_file = previous.as_ref().map(|previous| previous.path.join(&relative));
+        match previous_file {
+            Some(previous_file) if same_contents(file, &previous_file)? => {
+                // Fall back to a copy on file systems without hardlinks.
+                if fs::hard_link(&previous_file, &output_path).is_err() {
+                    copy_file(file, &output_path)?;
+                }
+            }
+            _ => {
+                copy_file(file, &output_path)?;
+                changed = true;
+            }
+        }
+    }
+
+    if !changed {
+        if temp_path.exists() {
+            fs::remove_dir_all(&temp_path)?;
+        }
+        return Ok(None);
+    }
+    if files.is_empty() {
+        fs::create_dir_all(&temp_path)?;
+    }
+    fs::rename(&temp_path, &path)?;
+    Ok(Some(Snapshot { taken_at: now, path }))
+}
+
+/// Points the `latest` view of the output directory `dir` at the DATs of `snapshot`.
+///
+/// The view holds one DAT per system at `<source>/<collection>/<system>.dat`, hardlinked into the
+/// snapshot. If a collection holds several versions of a DAT, such as `Nintendo - Game Boy (20200101-000000).dat`,
+/// the most recently modified one is used. DATs the organizer numbered because another DAT took their
+/// place, such as `Nintendo - Game Boy (2).dat`, are kept under their own name.
+pub fn update_latest<P: AsRef<Path>>(dir: P, snapshot: &Snapshot) -> Result<()> {
+    let dir = dir.as_ref();
+    let latest = dir.join(LATEST_DIR);
+    let temp_latest = dir.join(format!("{}.tmp", LATEST_DIR));
+
+    let mut newest = BTreeMap::new();
+    for file in list_files(&snapshot.path, &|_| true)? {
+        let relative = PathBuf::from(relative_path(&snapshot.path, &file));
+        let filename = relative.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let numbered = unnumbered_name(&filename).filter(|name| file.with_file_name(name).exists());
+        let view_path = match numbered {
+            Some(_) => relative.clone(),
+            None => relative.with_file_name(format!("{}.dat", system_name(&filename))),
+        };
+        let modified = fs::metadata(&file)?.modified()?;
+        match newest.get(&view_path) {
+            Some((newest_modified, _)) if *newest_modified >= modified => {}
+            _ => {
+                newest.insert(view_path, (modified, file));
+            }
+        }
+    }
+
+    if temp_latest.exists() {
+        fs::remove_dir_all(&temp_latest)?;
+    }
+    fs::create_dir_all(&temp_latest)?;
+    for (view_path, (_, file)) in newest {
+        let output_path = temp_latest.join(view_path);
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Fall back to a copy on file systems without hardlinks.
+        if fs::hard_link(&file, &output_path).is_err() {
+            copy_file(&file, &output_path)?;
+        }
+    }
+    if latest.exists() {
+        fs::remove_dir_all(&latest)?;
+    }
+    fs::rename(&temp_latest, &latest)?;
+    Ok(())
+}
+
+/// Removes the snapshots of the output directory `dir` that `retention` does not keep.
+///
+/// Returns the pruned snapshots.
+pub fn prune_snapshots<P: AsRef<Path>>(
+    dir: P,
+    retention: &Retention,
+    now: DateTime<Utc>,
+) -> Result<Vec<Snapshot>> {
+    let snapshots = list_snapshots(dir)?;
+    let expired = retention
+        .expired(&snapshots, now)
+        .into_iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    for snapshot in &expired {
+        fs::remove_dir_all(&snapshot.path)?;
+    }
+    Ok(expired)
+}
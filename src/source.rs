@@ -0,0 +1,283 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::header::{
+    HeaderMap, CONTENT_DISPOSITION, CONTENT_RANGE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::stream::Stream;
+
+/// Describes a single downloadable artifact offered by a source.
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    /// Identifies the artifact within its source. Stable across runs.
+    pub id: String,
+    /// The URL the artifact is requested from.
+    pub url: String,
+    /// Whether the artifact is only available to an authenticated session.
+    pub private: bool,
+    /// The release version of the artifact, if the source advertises one.
+    pub version: Option<String>,
+    /// Path prefixes of the files to extract from the downloaded archive.
+    ///
+    /// If set, only the matching files are kept and the archive itself is removed.
+    pub extract: Option<Vec<String>>,
+    /// The subdirectory of the output directory to save the artifact under, if any.
+    pub dir: Option<String>,
+    /// The collection the DATs in the artifact are organized under, such as `Non-Game`.
+    ///
+    /// DATs in a directory of the downloaded archive are organized under that directory instead.
+    pub collection: Option<String>,
+}
+
+/// The cache validators a server sent along with an artifact.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    /// The `ETag` header of the response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// The `Last-Modified` header of the response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// Reads the validators from the headers of a response.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_owned())
+        };
+        Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    /// Makes the request conditional on the resource having changed since these validators were sent.
+    pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = match &self.etag {
+            Some(etag) => request.header(IF_NONE_MATCH, etag),
+            None => request,
+        };
+        match &self.last_modified {
+            Some(last_modified) => request.header(IF_MODIFIED_SINCE, last_modified),
+            None => request,
+        }
+    }
+}
+
+/// A partial download to resume.
+#[derive(Debug, Clone)]
+pub struct Resume {
+    /// The number of bytes already downloaded.
+    pub offset: u64,
+    /// The validators of the response the partial download came from.
+    pub validators: Validators,
+}
+
+/// How to request an artifact.
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    /// Only download the artifact if it changed since it was saved with these validators.
+    pub if_modified: Option<Validators>,
+    /// Resume a partial download instead of starting from the first byte.
+    pub resume: Option<Resume>,
+}
+
+impl FetchOptions {
+    /// Adds the conditional and range headers for these options to the request.
+    ///
+    /// A partial download is only resumed if it has a validator to send with `If-Range`,
+    /// so that a changed artifact is downloaded in full instead of spliced onto the old one.
+    pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = match &self.if_modified {
+            Some(validators) => validators.apply(request),
+            None => request,
+        };
+        let resume = match &self.resume {
+            Some(resume) if resume.offset > 0 => resume,
+            _ => return request,
+        };
+        let if_range = resume
+            .validators
+            .etag
+            .as_ref()
+            .or(resume.validators.last_modified.as_ref());
+        match if_range {
+            Some(if_range) => request
+                .header(RANGE, format!("bytes={}-", resume.offset))
+                .header(IF_RANGE, if_range),
+            None => request,
+        }
+    }
+
+    /// The offset the body of the response starts at.
+    ///
+    /// This is the resume offset if the server honoured the range, or 0 if it sent the whole artifact.
+    pub fn offset_of(&self, response: &Response) -> Result<u64> {
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Ok(0);
+        }
+        let expected = self.resume.as_ref().map(|r| r.offset).unwrap_or(0);
+        let start = response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|r| r.to_str().ok())
+            .and_then(|r| r.strip_prefix("bytes "))
+            .and_then(|r| r.split('-').next())
+            .and_then(|start| start.parse::<u64>().ok());
+        match start {
+            Some(start) if start == expected => Ok(start),
+            _ => Err(anyhow!(
+                "Server resumed {} from an unexpected range",
+                response.url()
+            )),
+        }
+    }
+}
+
+/// A scraped page, saved so that it can be requested conditionally on the next run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CachedPage {
+    #[serde(flatten)]
+    pub validators: Validators,
+    pub body: String,
+}
+
+/// Saved pages of a source, keyed by URL.
+pub type PageCache = BTreeMap<String, CachedPage>;
+
+/// Sends the request, conditionally on the page having changed since `cached` was saved.
+///
+/// Returns the cached page if the server reports it has not changed.
+pub async fn fetch_cached_page(request: RequestBuilder, cached: Option<CachedPage>) -> Result<CachedPage> {
+    let request = match &cached {
+        Some(cached) => cached.validators.apply(request),
+        None => request,
+    };
+    let response = request.send().await?;
+    if let (StatusCode::NOT_MODIFIED, Some(cached)) = (response.status(), cached) {
+        return Ok(cached);
+    }
+    if !response.status().is_success() {
+        return Err(anyhow!("Unable to fetch {}: {}", response.url(), response.status()));
+    }
+    Ok(CachedPage {
+        validators: Validators::from_headers(response.headers()),
+        body: response.text().await?,
+    })
+}
+
+/// An in-flight artifact download.
+pub struct Download {
+    /// The filename the server suggested for the artifact.
+    pub filename: String,
+    /// The length of the artifact in bytes, or 0 if unknown.
+    pub length: u64,
+    /// The byte of the artifact the stream starts at, if a partial download is being resumed.
+    pub offset: u64,
+    /// The cache validators of the response the artifact is downloaded from.
+    pub validators: Validators,
+    pub stream: Pin<Box<dyn Stream<Item = Result<Bytes>>>>,
+}
+
+/// The filename a response suggests for its attachment.
+///
+/// The `filename` parameter of the Content-Disposition header is used if there is one, or else the
+/// last segment of the URL the response came from. Directories in either are dropped.
+pub fn attachment_filename(headers: &HeaderMap, url: &Url) -> Result<String> {
+    let disposition = headers
+        .get(CONTENT_DISPOSITION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            value.split(';').find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                let value = value.trim().trim_matches('"');
+                Some(value).filter(|value| key.trim().eq_ignore_ascii_case("filename") && !value.is_empty())
+            })
+        });
+    let last_segment = || url.path_segments().and_then(|segments| segments.rev().find(|s| !s.is_empty()));
+    disposition
+        .or_else(last_segment)
+        .and_then(|filename| filename.rsplit(['/', '\\']).next())
+        .filter(|filename| !filename.is_empty() && *filename != "." && *filename != "..")
+        .map(str::to_owned)
+        .ok_or_else(|| anyhow!("Unable to fetch attachment filename from {}", url))
+}
+
+/// The error a source fails a download with when the artifact turns out to require logging in.
+///
+/// Such artifacts are skipped like private artifacts rather than failing the sync.
+#[derive(Debug)]
+pub struct LoginRequired(pub String);
+
+impl fmt::Display for LoginRequired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} requires logging in", self.0)
+    }
+}
+
+impl std::error::Error for LoginRequired {}
+
+/// The outcome of attempting to authenticate with a source.
+#[derive(Debug)]
+pub enum Authentication {
+    /// Logged in as the given user.
+    LoggedIn(String),
+    /// Credentials were provided but rejected by the source.
+    InvalidCredentials,
+    /// No credentials were provided.
+    Anonymous,
+    /// The source does not support authentication.
+    Unsupported,
+}
+
+#[async_trait(?Send)]
+pub trait DatSource {
+    /// The name of the source, used for progress reporting.
+    fn name(&self) -> &'static str;
+
+    /// Attempts to get an authenticated session with the source.
+    async fn authenticate(&mut self) -> Result<Authentication> {
+        Ok(Authentication::Unsupported)
+    }
+
+    /// Enumerates the artifacts currently available from the source.
+    async fn artifacts(&self) -> Result<Vec<Artifact>>;
+
+    /// Starts downloading the given artifact.
+    async fn fetch(&self, artifact: &Artifact) -> Result<Download>;
+
+    /// Starts downloading the given artifact as described by `options`.
+    ///
+    /// Returns `None` if the artifact has not changed since it was saved. Sources that cannot make
+    /// conditional or range requests download the whole artifact regardless.
+    async fn fetch_with(
+        &self,
+        artifact: &Artifact,
+        _options: &FetchOptions,
+    ) -> Result<Option<Download>> {
+        self.fetch(artifact).await.map(Some)
+    }
+
+    /// Reuses the pages saved by a previous run, so that they can be requested conditionally.
+    fn restore_pages(&mut self, _pages: PageCache) {}
+
+    /// The pages scraped during this run, to be saved for the next.
+    fn saved_pages(&self) -> PageCache {
+        PageCache::new()
+    }
+
+    /// How long to wait after fetching an artifact to avoid being throttled.
+    fn throttle(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use zip::ZipArchive;
+
+use crate::catalog::Catalog;
+use crate::source::{PageCache, Validators};
+
+/// The name of the state manifest within the output directory.
+pub const STATE_FILENAME: &str = ".atsumare-state.json";
+
+lazy_static! {
+    static ref XML_HEADER_RE: Regex = Regex::new(r#"(?s)<header>(.*?)</header>"#).unwrap();
+    static ref XML_VERSION_RE: Regex = Regex::new(r#"<version>([^<]*)</version>"#).unwrap();
+    static ref CMP_HEADER_RE: Regex =
+        Regex::new(r#"(?ms)^\s*clrmamepro\s*\((.*?)^\s*\)"#).unwrap();
+    static ref CMP_VERSION_RE: Regex =
+        Regex::new(r#"(?m)^\s*version\s+(?:"([^"]*)"|(\S+))"#).unwrap();
+}
+
+/// What was saved for an artifact the last time it was downloaded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArtifactState {
+    /// The URL the artifact was requested from.
+    pub url: String,
+    /// When the artifact was downloaded.
+    pub fetched_at: DateTime<Utc>,
+    /// The path the artifact was saved to, relative to the output directory.
+    pub path: String,
+    /// The size of the downloaded artifact in bytes.
+    pub size: u64,
+    /// The hex encoded SHA-256 digest of the downloaded artifact.
+    pub sha256: String,
+    /// The cache validators the server sent with the artifact.
+    #[serde(default, flatten)]
+    pub validators: Validators,
+    /// The release version the source advertised for the artifact, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The version in the header of the downloaded DAT, if the artifact is a single DAT.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dat_version: Option<String>,
+    /// The files extracted or organized from the artifact, relative to the output directory.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+    /// The path prefixes the files were extracted with, if only part of the artifact was extracted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extract: Option<Vec<String>>,
+    /// Whether the DATs of the artifact were organized into the per-system tree.
+    #[serde(default)]
+    pub organized: bool,
+    /// The DATs in the artifact and their versions.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dats: Catalog,
+}
+
+impl ArtifactState {
+    /// Whether the artifact, or every file that was extracted from it, is still in the output directory `dir`.
+    pub fn is_saved<P: AsRef<Path>>(&self, dir: P) -> bool {
+        let dir = dir.as_ref();
+        dir.join(&self.path).exists()
+            || (!self.files.is_empty() && self.files.iter().all(|file| dir.join(file).exists()))
+    }
+
+    /// Whether the files were extracted with the path prefixes `extract`, in any order.
+    pub fn is_extracted_with(&self, extract: &Option<Vec<String>>) -> bool {
+        let sorted = |prefixes: &Option<Vec<String>>| {
+            prefixes.clone().map(|mut prefixes| {
+                prefixes.sort();
+                prefixes
+            })
+        };
+        sorted(&self.extract) == sorted(extract)
+    }
+
+    /// Whether the artifact was organized, or can still be organized from the archive in the
+    /// output directory `dir`, if `organize` is requested.
+    pub fn is_organized_with<P: AsRef<Path>>(&self, dir: P, organize: bool) -> bool {
+        !organize || self.organized || dir.as_ref().join(&self.path).exists()
+    }
+}
+
+/// A download that was interrupted before it completed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Partial {
+    /// The path of the partially downloaded file, relative to the output directory.
+    pub path: String,
+    /// The validators of the response the partial download came from.
+    #[serde(default, flatten)]
+    pub validators: Validators,
+}
+
+/// The state manifest of an output directory, recording every artifact downloaded into it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// The saved artifacts, keyed by source name and artifact ID.
+    #[serde(default)]
+    pub artifacts: BTreeMap<String, ArtifactState>,
+    /// The scraped pages of each source, keyed by source name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pages: BTreeMap<String, PageCache>,
+    /// Downloads in progress, keyed by source name and artifact ID.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub partials: BTreeMap<String, Partial>,
+    /// The DATs each source offered on its last sync, keyed by source name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub catalogs: BTreeMap<String, Catalog>,
+}
+
+impl State {
+    fn key(source: &str, id: &str) -> String {
+        format!("{}/{}", source, id)
+    }
+
+    /// Reads the state manifest of the output directory, or an empty state if there is none yet.
+    pub async fn load<P: AsRef<Path>>(dir: P) -> Result<State> {
+        let path = dir.as_ref().join(STATE_FILENAME);
+        match fs::read(&path).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("Unable to read state manifest {:?}", path)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes the state manifest into the output directory, replacing the previous manifest.
+    pub async fn save<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let path = dir.as_ref().join(STATE_FILENAME);
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_vec_pretty(self)?).await?;
+        fs::rename(&temp_path, &path).await?;
+        Ok(())
+    }
+
+    /// The saved state of the artifact of the given source.
+    pub fn get(&self, source: &str, id: &str) -> Option<&ArtifactState> {
+        self.artifacts.get(&State::key(source, id))
+    }
+
+    /// Records the state of the artifact of the given source, returning the previous state.
+    pub fn insert(&mut self, source: &str, id: &str, state: ArtifactState) -> Option<ArtifactState> {
+        self.artifacts.insert(State::key(source, id), state)
+    }
+
+    /// The interrupted download of the artifact of the given source, if any.
+    pub fn partial(&self, source: &str, id: &str) -> Option<&Partial> {
+        self.partials.get(&State::key(source, id))
+    }
+
+    /// Records that the artifact of the given source is being downloaded, or clears the record.
+    pub fn set_partial(&mut self, source: &str, id: &str, partial: Option<Partial>) {
+        let key = State::key(source, id);
+        match partial {
+            Some(partial) => self.partials.insert(key, partial),
+            None => self.partials.remove(&key),
+        };
+    }
+}
+
+/// The hex encoded SHA-256 digest of the file at `path`.
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Reads the header version of the DAT at `path`.
+///
+/// ZIP archives are looked into if they contain exactly one DAT. Packs of several DATs have no single version.
+pub fn dat_version<P: AsRef<Path>>(path: P) -> Result<Option<String>> {
+    let mut file = File::open(path.as_ref())?;
+    let mut magic = [0; 4];
+    let is_zip = file.read(&mut magic)? == 4 && magic == *b"PK\x03\x04";
+    drop(file);
+
+    let contents = if is_zip {
+        let mut zip = ZipArchive::new(File::open(path.as_ref())?)?;
+        let dats = zip
+            .file_names()
+            .filter(|name| is_dat_name(name))
+            .map(|name| name.to_owned())
+            .collect::<Vec<_>>();
+        match dats.as_slice() {
+            [name] => {
+                let mut contents = Vec::new();
+                zip.by_name(name)?.read_to_end(&mut contents)?;
+                contents
+            }
+            _ => return Ok(None),
+        }
+    } else if is_dat_name(&path.as_ref().to_string_lossy()) {
+        std::fs::read(path.as_ref())?
+    } else {
+        return Ok(None);
+    };
+
+    Ok(parse_dat_version(&String::from_utf8_lossy(&contents)))
+}
+
+/// Whether the file looks like a DAT by its name.
+pub(crate) fn is_dat_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".dat") || name.ends_with(".xml")
+}
+
+/// Finds the header version of a Logiqx XML or ClrMamePro DAT.
+///
+/// Games can have versions of their own, such as in Redump DATs with serials and versions,
+/// so only the header is looked at.
+pub fn parse_dat_version(contents: &str) -> Option<String> {
+    if let Some(header) = XML_HEADER_RE.captures(contents) {
+        return XML_VERSION_RE
+            .captures(&header[1])
+            .map(|captures| captures[1].trim().to_owned());
+    }
+    let header = CMP_HEADER_RE
+        .captures(contents)
+        .and_then(|header| header.get(1))
+        .map(|header| header.as_str())
+        .unwrap_or(contents);
+    CMP_VERSION_RE
+        .captures(header)
+        .and_then(|c| c.get(1).or_else(|| c.get(2)))
+        .map(|m| m.as_str().to_owned())
+}
+
+/// The path of `path` relative to `dir`, for recording in the state manifest.
+pub fn relative_path<P: AsRef<Path>, Q: AsRef<Path>>(dir: P, path: Q) -> String {
+    let path = path.as_ref();
+    path.strip_prefix(dir.as_ref())
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
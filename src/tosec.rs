@@ -1,14 +1,164 @@
 use anyhow::{anyhow, Error, Result};
-use bytes::Bytes;
+use async_trait::async_trait;
 use futures_util::TryStreamExt;
-use reqwest::ClientBuilder;
-use tokio::stream::Stream;
-use std::pin::Pin;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::{ClientBuilder, StatusCode, Url};
+use scraper::{Html, Selector};
+use std::str::FromStr;
+use crate::source::{attachment_filename, Artifact, DatSource, Download, FetchOptions, Validators};
 
-const HTTPS_DOWNLOAD: &str = "https://www.tosecdev.org/downloads/category/50-2020-07-29?download=99:tosec-dat-pack-complete-3036-tosec-v2020-07-29";
+const HTTPS_ROOT: &str = "https://www.tosecdev.org";
+const DATFILES_PATH: &str = "/downloads/category/22-datfiles";
 
-pub async fn fetch_zip() -> Result<(String, u64, Pin<Box<dyn Stream<Item = Result<Bytes>>>>)> {
-    let download_req = ClientBuilder::new().build()?.get(HTTPS_DOWNLOAD).send().await?;
+lazy_static! {
+    static ref RELEASE_RE: Regex =
+        Regex::new(r#"/downloads/category/[0-9]+-([0-9]{4}-[0-9]{2}-[0-9]{2})/?$"#).unwrap();
+    static ref PACK_RE: Regex =
+        Regex::new(r#"[?&]download=[0-9]+:tosec-dat-pack-complete-"#).unwrap();
+}
+
+/// Where tosecdev.org lists its dated DAT pack releases.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    /// The downloads category listing every dated DAT release.
+    pub datfiles: String,
+}
+
+impl Endpoints {
+    /// Derives the endpoints from a site root such as `http://localhost:8080`.
+    pub fn with_root<S: AsRef<str>>(mut self, root: S) -> Self {
+        self.datfiles = format!("{}{}", root.as_ref().trim_end_matches('/'), DATFILES_PATH);
+        self
+    }
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            datfiles: format!("{}{}", HTTPS_ROOT, DATFILES_PATH),
+        }
+    }
+}
+
+/// The collections bundled in the TOSEC complete DAT pack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collection {
+    /// Cartridge, disk and tape images.
+    Tosec,
+    /// CD, DVD and other optical media images.
+    TosecIso,
+    /// Scans of manuals, boxes and other printed material.
+    TosecPix,
+}
+
+impl Collection {
+    /// The directory the collection is stored under in the DAT pack.
+    pub fn dir(&self) -> &'static str {
+        match self {
+            Collection::Tosec => "TOSEC",
+            Collection::TosecIso => "TOSEC-ISO",
+            Collection::TosecPix => "TOSEC-PIX",
+        }
+    }
+}
+
+impl FromStr for Collection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "TOSEC" => Ok(Collection::Tosec),
+            "TOSEC-ISO" | "ISO" => Ok(Collection::TosecIso),
+            "TOSEC-PIX" | "PIX" => Ok(Collection::TosecPix),
+            _ => Err(anyhow!("Unknown TOSEC collection {}", s)),
+        }
+    }
+}
+
+/// A dated TOSEC DAT release.
+#[derive(Debug, Clone)]
+pub struct Release {
+    /// The release date, such as `2020-07-29`. TOSEC versions its packs by date.
+    pub date: String,
+    /// The downloads category page for the release.
+    pub url: String,
+}
+
+async fn fetch_page(url: &str) -> Result<Html> {
+    let page = ClientBuilder::new().build()?.get(url).send().await?;
+    if !page.status().is_success() {
+        return Err(anyhow!("Unable to fetch {}: {}", url, page.status()));
+    }
+    Ok(Html::parse_document(&page.text().await?))
+}
+
+fn anchors(page: &Html, base: &str) -> Result<Vec<String>> {
+    let selector = Selector::parse("a")
+        .map_err(|_| anyhow!("Unable to parse selector! This should never happen!!"))?;
+    let base = Url::parse(base)?;
+    Ok(page
+        .select(&selector)
+        .filter_map(|n| n.value().attr("href"))
+        .filter_map(|href| base.join(href).ok())
+        .map(|url| url.to_string())
+        .collect())
+}
+
+/// Lists the dated DAT releases on the TOSEC downloads page, newest first.
+pub async fn fetch_releases(endpoints: &Endpoints) -> Result<Vec<Release>> {
+    let page = fetch_page(&endpoints.datfiles).await?;
+    let mut releases = anchors(&page, &endpoints.datfiles)?
+        .into_iter()
+        .filter_map(|url| {
+            let date = RELEASE_RE.captures(&url)?.get(1)?.as_str().to_owned();
+            Some(Release { date, url })
+        })
+        .collect::<Vec<_>>();
+
+    releases.sort_by(|a, b| b.date.cmp(&a.date));
+    releases.dedup_by(|a, b| a.date == b.date);
+
+    if releases.is_empty() {
+        return Err(anyhow!(
+            "Unable to find any TOSEC releases at {}. The downloads page may have changed.",
+            endpoints.datfiles
+        ));
+    }
+    Ok(releases)
+}
+
+/// Finds the complete DAT pack download on the page of a release.
+pub async fn fetch_pack_url(release: &Release) -> Result<String> {
+    let page = fetch_page(&release.url).await?;
+    anchors(&page, &release.url)?
+        .into_iter()
+        .find(|url| PACK_RE.is_match(url))
+        .ok_or_else(|| {
+            anyhow!(
+                "Unable to find the complete DAT pack for TOSEC release {}. The release page may have changed.",
+                release.date
+            )
+        })
+}
+
+pub async fn fetch_zip<S: AsRef<str>>(download_url: S) -> Result<Download> {
+    fetch_zip_with(download_url, &FetchOptions::default())
+        .await?
+        .ok_or_else(|| anyhow!("Server reported the download as not modified"))
+}
+
+/// Starts downloading the pack as described by `options`, unless it has not changed since it was saved.
+pub async fn fetch_zip_with<S: AsRef<str>>(
+    download_url: S,
+    options: &FetchOptions,
+) -> Result<Option<Download>> {
+    let download_req = ClientBuilder::new().build()?.get(download_url.as_ref());
+    let download_req = options.apply(download_req).send().await?;
+    if download_req.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    let offset = options.offset_of(&download_req)?;
 
     let headers = download_req.headers();
 
@@ -20,15 +170,92 @@ pub async fn fetch_zip() -> Result<(String, u64, Pin<Box<dyn Stream<Item = Resul
         return Err(anyhow!("Response was not a valid ZIP archive"));
     }
 
-    let content_diposition = headers
-        .get("content-disposition")
-        .and_then(|s| s.to_str().map(|s| s.to_string()).ok())
-        .map(|s| String::from(&s["attachment; filename=\"".len()..s.len() - 1]))
-        .ok_or(anyhow!("Unable to fetch attachment filename"))?;
+    let content_diposition = attachment_filename(headers, download_req.url())?;
+
+    Ok(Some(Download {
+        filename: content_diposition,
+        length: download_req.content_length().map(|l| offset + l).unwrap_or(0),
+        offset,
+        validators: Validators::from_headers(download_req.headers()),
+        stream: Box::pin(download_req.bytes_stream().map_err(Error::new)),
+    }))
+}
+
+/// The TOSEC complete DAT pack.
+#[derive(Default)]
+pub struct Tosec {
+    endpoints: Endpoints,
+    release: Option<String>,
+    collections: Option<Vec<Collection>>,
+}
+
+impl Tosec {
+    pub fn new() -> Self {
+        Tosec::default()
+    }
+
+    /// Use the given endpoints instead of tosecdev.org.
+    pub fn with_endpoints(mut self, endpoints: Endpoints) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// Download the release from the given date, such as `2020-07-29`, instead of the newest release.
+    pub fn with_release<S: Into<String>>(mut self, date: S) -> Self {
+        self.release = Some(date.into());
+        self
+    }
+
+    /// Extract only the DATs of the given collections instead of storing the whole pack.
+    pub fn with_collections(mut self, collections: Vec<Collection>) -> Self {
+        self.collections = Some(collections);
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl DatSource for Tosec {
+    fn name(&self) -> &'static str {
+        "TOSEC"
+    }
+
+    async fn artifacts(&self) -> Result<Vec<Artifact>> {
+        let releases = fetch_releases(&self.endpoints).await?;
+        let release = match &self.release {
+            Some(date) => releases
+                .into_iter()
+                .find(|r| &r.date == date)
+                .ok_or_else(|| anyhow!("TOSEC release {} was not found", date))?,
+            None => releases.into_iter().next().unwrap(),
+        };
+
+        let url = fetch_pack_url(&release).await?;
+        Ok(vec![Artifact {
+            id: String::from("pack"),
+            url,
+            private: false,
+            version: Some(release.date),
+            extract: self.collections.as_ref().map(|collections| {
+                collections
+                    .iter()
+                    .map(|c| format!("{}/", c.dir()))
+                    .collect()
+            }),
+            dir: None,
+            // The pack groups its DATs into a directory per collection.
+            collection: None,
+        }])
+    }
 
-    Ok((
-        content_diposition,
-        download_req.content_length().unwrap_or(0),
-        Box::pin(download_req.bytes_stream().map_err(|e| Error::new(e))),
-    ))
+    async fn fetch(&self, artifact: &Artifact) -> Result<Download> {
+        fetch_zip(&artifact.url).await
+    }
+
+    async fn fetch_with(
+        &self,
+        artifact: &Artifact,
+        options: &FetchOptions,
+    ) -> Result<Option<Download>> {
+        fetch_zip_with(&artifact.url, options).await
+    }
 }
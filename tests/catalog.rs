@@ -0,0 +1,71 @@
+//! Checks listing the DATs of downloads and comparing catalogs.
+
+mod common;
+
+use atsumare::catalog::{catalog_artifact, diff_catalogs};
+use atsumare::diff::Report;
+use common::{output_dir, write_zip};
+use std::io::Write;
+use zip::write::{FileOptions, ZipWriter};
+
+const NEW_DAT: &str = include_str!("fixtures/game-boy-20200202.dat");
+
+#[test]
+fn catalog_lists_pack_dats() {
+    let dir = output_dir("catalog-pack");
+    let pack = dir.join("No-Intro Love Pack (PC XML) (20200202).zip");
+    write_zip(
+        &pack,
+        &[
+            ("Nintendo - Game Boy (20200202-000000).dat", NEW_DAT),
+            ("Nintendo - Pokemon Mini (20200202-000000).dat", "no header"),
+            ("README.txt", "not a DAT"),
+        ],
+    );
+
+    let catalog = catalog_artifact(&pack, "No-Intro", |_| true, "unused").unwrap();
+    assert_eq!(
+        catalog.keys().collect::<Vec<_>>(),
+        vec![
+            "No-Intro/Nintendo - Game Boy",
+            "No-Intro/Nintendo - Pokemon Mini"
+        ]
+    );
+    assert_eq!(catalog["No-Intro/Nintendo - Game Boy"], "20200202-000000");
+    assert!(catalog["No-Intro/Nintendo - Pokemon Mini"].starts_with("crc32 "));
+
+    let mut old = catalog.clone();
+    old.remove("No-Intro/Nintendo - Pokemon Mini");
+    old.insert(
+        String::from("No-Intro/Nintendo - Game Boy"),
+        String::from("20200101-000000"),
+    );
+    old.insert(
+        String::from("No-Intro/Nintendo - Virtual Boy"),
+        String::from("20200101-000000"),
+    );
+    let changes = diff_catalogs("No-Intro", &old, &catalog);
+    assert_eq!(changes.appeared, vec!["No-Intro/Nintendo - Pokemon Mini"]);
+    assert_eq!(changes.disappeared, vec!["No-Intro/Nintendo - Virtual Boy"]);
+    assert_eq!(changes.changed[0].from, "20200101-000000");
+    assert_eq!(
+        changes.to_text().lines().next().unwrap(),
+        "No-Intro: 1 appeared, 1 disappeared, 1 changed version"
+    );
+}
+
+#[test]
+fn catalog_reads_latin1_headers() {
+    let dir = output_dir("catalog-latin1");
+    let pack = dir.join("TOSEC - DAT Pack - Complete (2020-02-02).zip");
+    let header = NEW_DAT.replace("Nintendo - Game Boy", "Nintendo - Game Boy (Pok\u{e9}mon)");
+    let latin1 = header.chars().map(|c| c as u8).collect::<Vec<_>>();
+    let mut zip = ZipWriter::new(std::fs::File::create(&pack).unwrap());
+    zip.start_file("TOSEC/Nintendo Game Boy - Games (TOSEC-v2020-02-02).dat", FileOptions::default())
+        .unwrap();
+    zip.write_all(&latin1).unwrap();
+    zip.finish().unwrap();
+
+    let catalog = catalog_artifact(&pack, "TOSEC", |_| true, "unused").unwrap();
+    assert_eq!(catalog.values().collect::<Vec<_>>(), vec!["20200202-000000"]);
+}
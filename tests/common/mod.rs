@@ -0,0 +1,34 @@
+//! Helpers shared by the integration tests.
+
+// Every test crate includes this module, but none of them uses all of it.
+#![allow(dead_code)]
+
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use zip::write::{FileOptions, ZipWriter};
+
+/// An empty but well-formed ZIP archive.
+pub const EMPTY_ZIP: &[u8] = b"PK\x05\x06\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+
+/// Creates an empty directory for the test `name` to write to.
+pub fn output_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("atsumare-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Builds a ZIP archive of the given files.
+pub fn zip_of(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        zip.start_file(*name, FileOptions::default()).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+/// Writes a ZIP archive of the given files to `path`.
+pub fn write_zip<P: AsRef<Path>>(path: P, files: &[(&str, &str)]) {
+    std::fs::write(path, zip_of(files)).unwrap();
+}
@@ -0,0 +1,289 @@
+//! Checks reading and writing DATs in every dialect.
+
+mod common;
+
+use atsumare::convert::{convert_to_xml_dat, parse_cmp_dat, read_dat, read_dat_file, write_dat};
+use atsumare::dat::Dialect;
+use atsumare::diff::read_games;
+use atsumare::state::parse_dat_version;
+use common::output_dir;
+
+const FULL_CMP_DAT: &str = include_str!("fixtures/arcade-test.dat");
+
+#[test]
+fn convert_keeps_every_field() {
+    let dat = parse_cmp_dat(FULL_CMP_DAT).unwrap();
+    assert_eq!(dat.header.force.len(), 2);
+    assert_eq!(dat.games.len(), 2);
+    let game = &dat.games[1];
+    assert_eq!(game.serial.as_deref(), Some("T-12345"));
+    assert_eq!(game.roms[1].status.as_deref(), Some("nodump"));
+    assert_eq!(game.roms[1].crc, None);
+
+    let xml = convert_to_xml_dat(FULL_CMP_DAT, "example.com", None).unwrap();
+    let xml = std::str::from_utf8(&xml).unwrap();
+    for expected in &[
+        "<date>2020-01-01</date>",
+        "<homepage>example.com</homepage>",
+        "<category>Arcade</category>\n\t\t<description>Test Game (Europe)</description>",
+        "<comment>Everything the format can hold</comment>",
+        r#"<clrmamepro forcemerging="full" forcenodump="obsolete"/>"#,
+        r#"<game name="Neo Geo BIOS" isbios="yes">"#,
+        r#"<biosset name="euro" description="Europe MVS" default="yes"/>"#,
+        r#"<game name="Test Game (Europe)" cloneof="Test Game (USA)" romof="neogeo" sampleof="testgame" board="MVS">"#,
+        "<comment>Verified by two dumpers</comment>",
+        "<year>1994</year>",
+        "<manufacturer>Test Co</manufacturer>",
+        "<serial>T-12345</serial>",
+        "<version>1.01</version>",
+        "<region>Europe</region>",
+        r#"<release name="Test Game (Europe)" region="EUR" language="En"/>"#,
+        r#"status="baddump""#,
+        r#"<rom name="missing.bin" size="512" status="nodump"/>"#,
+        r#"crc="89abcdef" sha256="0011223344556677889900112233445566778899001122334455667788990011" merge="shared.bin""#,
+        r#"<disk name="test" sha1="fedcba9876543210fedcba9876543210fedcba98"/>"#,
+        r#"<sample name="bang"/>"#,
+        r#"<video screen="raster" orientation="horizontal"/>"#,
+    ] {
+        assert!(
+            xml.contains(expected),
+            "{} is missing from\n{}",
+            expected,
+            xml
+        );
+    }
+
+    assert_eq!(parse_dat_version(xml).as_deref(), Some("20200101"));
+    let headerless = FULL_CMP_DAT.replace("\tversion 20200101\n", "");
+    assert_eq!(parse_dat_version(&headerless), None);
+
+    let games = read_games(xml).unwrap();
+    assert_eq!(games.len(), 2);
+    assert_eq!(games[1].roms.len(), 4);
+}
+
+#[test]
+fn convert_names_games_after_their_description() {
+    let cmp = r#"clrmamepro (
+	name "Test"
+)
+
+game (
+	name "tg"
+	description "Test Game (Europe)"
+	rom ( name "a.bin" size 1 crc 00000000 md5 d41d8cd98f00b204e9800998ecf8427e sha1 da39a3ee5e6b4b0d3255bfef95601890afd80709 )
+)
+"#;
+    let xml = convert_to_xml_dat(cmp, "example.com", None).unwrap();
+    let xml = std::str::from_utf8(&xml).unwrap();
+    assert!(xml.contains(r#"<game name="Test Game (Europe)">"#), "{}", xml);
+
+    let xml = convert_to_xml_dat(cmp, "example.com", Some(|name| name.to_uppercase())).unwrap();
+    let xml = std::str::from_utf8(&xml).unwrap();
+    assert!(xml.contains(r#"<game name="TEST GAME (EUROPE)">"#), "{}", xml);
+
+    let dat = parse_cmp_dat(cmp).unwrap();
+    let xml = write_dat(&dat, Dialect::Logiqx).unwrap();
+    assert!(std::str::from_utf8(&xml).unwrap().contains(r#"<game name="tg">"#));
+}
+
+#[test]
+fn convert_tolerates_missing_rom_fields() {
+    let dat = r#"clrmamepro (
+	name "Atari - 2600 - Games"
+	version 2020-07-29
+)
+
+game (
+	name "Adventure (1980)(Atari)"
+	rom ( name "Adventure (1980)(Atari).a26" size 4096 crc 157bddb7 )
+)
+
+game (
+	name "Unreleased (Proto)"
+	rom ( name "Unreleased (Proto).a26" size 4096 crc - md5 - flags nodump )
+)
+
+game (
+	name "Homebrew (2020)"
+	rom ( name "Homebrew (2020).a26" size 4096 sha256 00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff xxh3_64 0123456789abcdef xxh3_128 0123456789abcdef0123456789abcdef )
+)
+"#;
+
+    let xml = convert_to_xml_dat(dat, "tosecdev.org", None).unwrap();
+    let xml = std::str::from_utf8(&xml).unwrap();
+    assert!(xml.contains(r#"<rom name="Adventure (1980)(Atari).a26" size="4096" crc="157bddb7"/>"#));
+    assert!(xml.contains(r#"<rom name="Unreleased (Proto).a26" size="4096" status="nodump"/>"#));
+    assert!(xml.contains(
+        r#"sha256="00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff" xxh3_64="0123456789abcdef" xxh3_128="0123456789abcdef0123456789abcdef"/>"#
+    ));
+
+    let games = read_games(xml).unwrap();
+    assert_eq!(games[1].roms[0].crc, None);
+    assert_eq!(
+        games[2].roms[0].sha256.as_deref(),
+        Some("00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff")
+    );
+}
+
+#[test]
+fn dats_round_trip_between_dialects() {
+    let dat = parse_cmp_dat(FULL_CMP_DAT).unwrap();
+
+    let cmp = write_dat(&dat, Dialect::ClrMamePro).unwrap();
+    let cmp = std::str::from_utf8(&cmp).unwrap();
+    assert_eq!(Dialect::detect(cmp), Dialect::ClrMamePro);
+    assert_eq!(read_dat(cmp).unwrap(), dat);
+
+    let xml = write_dat(&dat, Dialect::Logiqx).unwrap();
+    let xml = std::str::from_utf8(&xml).unwrap();
+    assert_eq!(Dialect::detect(xml), Dialect::Logiqx);
+    let from_xml = read_dat(xml).unwrap();
+    assert_eq!(from_xml, dat);
+
+    let cmp = write_dat(&from_xml, Dialect::ClrMamePro).unwrap();
+    let cmp = std::str::from_utf8(&cmp).unwrap();
+    for expected in &[
+        "\tforcemerging \"full\"\n",
+        "resource (\n\tname \"neogeo\"\n",
+        "\tbiosset ( name \"euro\" description \"Europe MVS\" default \"yes\" )\n",
+        "\trom ( name \"test.bin\" size 1024 crc 01234567 md5 0123456789abcdef0123456789abcdef sha1 0123456789abcdef0123456789abcdef01234567 flags baddump )\n",
+        "\tvideo ( screen \"raster\" orientation \"horizontal\" )\n",
+    ] {
+        assert!(cmp.contains(expected), "{} is missing from\n{}", expected, cmp);
+    }
+}
+
+#[test]
+fn clrmamepro_dats_reject_double_quotes() {
+    let mut dat = parse_cmp_dat(FULL_CMP_DAT).unwrap();
+    dat.games[1].description = Some(String::from("Test \"Game\" (Europe)"));
+
+    let error = write_dat(&dat, Dialect::ClrMamePro).unwrap_err();
+    assert!(error.to_string().contains("double quotes"));
+    let xml = write_dat(&dat, Dialect::Logiqx).unwrap();
+    assert_eq!(read_dat(std::str::from_utf8(&xml).unwrap()).unwrap(), dat);
+}
+
+#[test]
+fn logiqx_dats_keep_unknown_fields() {
+    let xml = r#"<?xml version="1.0"?>
+<datafile>
+	<header>
+		<name>Nintendo - Game Boy</name>
+		<version>20200729-000000</version>
+		<author>No-Intro &amp; friends</author>
+		<clrmamepro header="No-Intro_GB.xml"/>
+		<romcenter plugin="arcade.dll"/>
+	</header>
+	<machine name="Tetris (World)" isdevice="no">
+		<description>Tetris (World)</description>
+		<publisher region="EU">Nintendo</publisher>
+		<rom name="Tetris (World).gb" size="32768" crc="46DF91AD" status="verified"/>
+	</machine>
+	<game name="Empty"/>
+</datafile>
+"#;
+    let dat = read_dat(xml).unwrap();
+    assert_eq!(dat.header.author.as_deref(), Some("No-Intro & friends"));
+    assert_eq!(
+        dat.header.force,
+        vec![(String::from("header"), String::from("No-Intro_GB.xml"))]
+    );
+    assert_eq!(dat.games.len(), 2);
+    assert_eq!(
+        dat.games[0].attributes,
+        vec![(String::from("isdevice"), String::from("no"))]
+    );
+    assert_eq!(dat.games[0].roms[0].status.as_deref(), Some("verified"));
+    assert_eq!(dat.games[1].name, "Empty");
+
+    let rewritten = write_dat(&dat, Dialect::Logiqx).unwrap();
+    let rewritten = std::str::from_utf8(&rewritten).unwrap();
+    assert!(rewritten.contains(r#"<game name="Tetris (World)" isdevice="no">"#));
+    assert!(rewritten.contains(r#"<clrmamepro header="No-Intro_GB.xml"/>"#));
+    assert!(rewritten.contains(r#"<romcenter plugin="arcade.dll"/>"#));
+    assert!(rewritten.contains(r#"<publisher region="EU">Nintendo</publisher>"#));
+
+    let cmp = write_dat(&dat, Dialect::ClrMamePro).unwrap();
+    let cmp = std::str::from_utf8(&cmp).unwrap();
+    assert!(cmp.contains("\tpublisher ( region \"EU\" text \"Nintendo\" )\n"));
+    // Game attributes are still attributes after a round trip through ClrMamePro.
+    let from_cmp = write_dat(&read_dat(cmp).unwrap(), Dialect::Logiqx).unwrap();
+    assert!(std::str::from_utf8(&from_cmp)
+        .unwrap()
+        .contains(r#"<game name="Tetris (World)" isdevice="no">"#));
+    let games = read_games(cmp).unwrap();
+    assert_eq!(games[0].roms[0].crc.as_deref(), Some("46df91ad"));
+}
+
+const RC_DAT: &str = "[CREDITS]\r
+author=tester\r
+version=0.37b10\r
+comment=Converted for RomCenter\r
+[DAT]\r
+version=2.50\r
+plugin=arcade.dll\r
+split=1\r
+merge=0\r
+[EMULATOR]\r
+refname=MAME\r
+version=MAME 0.37b10\r
+[RESOURCES]\r
+¬neogeo¬Neo Geo BIOS¬neogeo¬Neo Geo BIOS¬sp-s2.sp1¬9036d879¬131072¬¬¬\r
+[GAMES]\r
+¬mslug¬Metal Slug¬mslug¬Metal Slug¬201-p1.bin¬08d8daa5¬2097152¬neogeo¬¬\r
+¬mslug¬Metal Slug¬mslug¬Metal Slug¬201-s1.bin¬2f55958d¬131072¬neogeo¬¬\r
+¬mslug¬Metal Slug¬mslugb¬Metal Slug (bootleg)¬201-s1.bin¬2f55958d¬131072¬neogeo¬201-s1.bin¬\r
+";
+
+#[test]
+fn romcenter_dats_convert() {
+    let dat = read_dat(RC_DAT).unwrap();
+    assert_eq!(dat.header.name.as_deref(), Some("MAME"));
+    assert_eq!(dat.header.emulator_version.as_deref(), Some("MAME 0.37b10"));
+    assert_eq!(dat.header.description, None);
+    assert_eq!(dat.header.version.as_deref(), Some("0.37b10"));
+    assert_eq!(
+        dat.header.force,
+        vec![(String::from("forcemerging"), String::from("split"))]
+    );
+    assert_eq!(dat.games.len(), 3);
+    assert!(dat.games[0].is_bios);
+    assert!(!dat.games[1].is_bios);
+    assert_eq!(dat.games[1].roms.len(), 2);
+    assert_eq!(dat.games[1].cloneof, None);
+    assert_eq!(dat.games[1].romof.as_deref(), Some("neogeo"));
+    assert_eq!(dat.games[2].cloneof.as_deref(), Some("mslug"));
+    assert_eq!(dat.games[2].roms[0].merge.as_deref(), Some("201-s1.bin"));
+    assert_eq!(dat.games[2].roms[0].size.as_deref(), Some("131072"));
+
+    let xml = write_dat(&dat, Dialect::Logiqx).unwrap();
+    let xml = std::str::from_utf8(&xml).unwrap();
+    assert!(xml.contains(r#"<game name="mslugb" cloneof="mslug" romof="neogeo">"#));
+    assert!(
+        xml.contains(r#"<rom name="201-s1.bin" size="131072" crc="2f55958d" merge="201-s1.bin"/>"#)
+    );
+    assert!(xml.contains(r#"<romcenter plugin="arcade.dll"/>"#));
+    assert!(xml.contains(r#"<game name="neogeo" isbios="yes">"#));
+
+    let rc = write_dat(&dat, Dialect::RomCenter).unwrap();
+    // RomCenter DATs are written in Latin-1, where the separator is a single byte.
+    assert!(rc.iter().all(|&b| b != 0xc2));
+    let rc = rc.iter().map(|&b| b as char).collect::<String>();
+    assert_eq!(rc, RC_DAT);
+
+    // Logiqx has no place for the emulator version, so the DAT name stands in for it.
+    let rc = write_dat(&read_dat(xml).unwrap(), Dialect::RomCenter).unwrap();
+    let rc = rc.iter().map(|&b| b as char).collect::<String>();
+    assert_eq!(rc, RC_DAT.replace("version=MAME 0.37b10", "version=MAME"));
+
+    let mut unicode = dat.clone();
+    unicode.games[1].description = Some(String::from("メタルスラッグ"));
+    assert!(write_dat(&unicode, Dialect::RomCenter).is_err());
+
+    let path = output_dir("romcenter").join("mame.dat");
+    let latin1 = RC_DAT.chars().map(|c| c as u8).collect::<Vec<_>>();
+    std::fs::write(&path, latin1).unwrap();
+    assert_eq!(read_dat_file(&path).unwrap(), dat);
+}
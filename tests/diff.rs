@@ -0,0 +1,103 @@
+//! Checks comparing the games of DATs and snapshots.
+
+mod common;
+
+use atsumare::diff::{diff_games, diff_snapshots, read_games, Rename, Report, ReportFormat};
+use common::{output_dir, write_zip};
+
+const OLD_DAT: &str = include_str!("fixtures/game-boy-20200101.xml");
+const NEW_DAT: &str = include_str!("fixtures/game-boy-20200202.dat");
+
+#[test]
+fn diff_reports_game_changes() {
+    let old = read_games(OLD_DAT).unwrap();
+    let new = read_games(NEW_DAT).unwrap();
+    let diff = diff_games(&old, &new);
+
+    assert_eq!(diff.added, vec!["Delta (World)"]);
+    assert_eq!(diff.removed, vec!["Gamma & Co (Europe)"]);
+    assert_eq!(
+        diff.renamed,
+        vec![Rename {
+            from: String::from("Beta (Japan)"),
+            to: String::from("Beta (Japan) (Rev 1)"),
+        }]
+    );
+    assert_eq!(diff.changed.len(), 1);
+    let rom = &diff.changed[0].roms[0];
+    assert_eq!(rom.old.as_ref().unwrap().crc.as_deref(), Some("aaaaaaaa"));
+    assert_eq!(rom.new.as_ref().unwrap().crc.as_deref(), Some("abababab"));
+
+    let text = diff.render(ReportFormat::Text).unwrap();
+    assert!(text.starts_with("1 added, 1 removed, 1 renamed, 1 changed\n"));
+    assert!(text.contains("> Beta (Japan) -> Beta (Japan) (Rev 1)\n"));
+    assert!(text.contains("crc aaaaaaaa -> abababab, sha1 aa -> ab"));
+    let markdown = diff.render(ReportFormat::Markdown).unwrap();
+    assert!(markdown.contains("## Renamed (1)\n\n- Beta (Japan) → Beta (Japan) (Rev 1)\n"));
+    let json: serde_json::Value =
+        serde_json::from_str(&diff.render(ReportFormat::Json).unwrap()).unwrap();
+    assert_eq!(json["removed"][0], "Gamma & Co (Europe)");
+
+    assert!(diff_games(&new, &new).is_empty());
+
+    // A DAT that stops listing a hash does not change the ROM.
+    let mut unhashed = old.clone();
+    unhashed[0].roms[0].sha1 = None;
+    assert!(diff_games(&old, &unhashed).is_empty());
+    let mut changed = unhashed.clone();
+    changed[0].roms[0].crc = Some(String::from("abababab"));
+    let text = diff_games(&old, &changed)
+        .render(ReportFormat::Text)
+        .unwrap();
+    assert!(text.contains("~ Alpha (USA).gb: crc aaaaaaaa -> abababab\n"));
+}
+
+#[test]
+fn diff_matches_snapshot_dats_by_system() {
+    let dir = output_dir("diff-snapshots");
+    let old = dir.join("old");
+    let new = dir.join("new");
+    std::fs::create_dir_all(old.join("redump")).unwrap();
+    std::fs::create_dir_all(new.join("redump")).unwrap();
+
+    write_zip(
+        old.join("No-Intro Love Pack (PC XML) (20200101).zip"),
+        &[
+            ("Nintendo - Game Boy (20200101-000000).dat", OLD_DAT),
+            ("Nintendo - Virtual Boy (20200101-000000).dat", OLD_DAT),
+        ],
+    );
+    write_zip(
+        new.join("No-Intro Love Pack (PC XML) (20200202).zip"),
+        &[
+            ("Nintendo - Game Boy (20200202-000000).dat", NEW_DAT),
+            ("Nintendo - Pokemon Mini (20200202-000000).dat", NEW_DAT),
+        ],
+    );
+    std::fs::write(old.join("redump/Sony - PlayStation.dat"), OLD_DAT).unwrap();
+    std::fs::write(new.join("redump/Sony - PlayStation.dat"), OLD_DAT).unwrap();
+    std::fs::write(old.join("redump/Sega - Saturn.dat"), OLD_DAT).unwrap();
+    std::fs::write(
+        new.join("redump/Sega - Saturn.dat"),
+        "<datafile><game name=",
+    )
+    .unwrap();
+
+    let diff = diff_snapshots(&old, &new).unwrap();
+    assert_eq!(diff.unreadable.len(), 1);
+    assert!(diff.unreadable[0].starts_with("new redump/Sega - Saturn.dat: "));
+    assert!(diff
+        .render(ReportFormat::Text)
+        .unwrap()
+        .contains("! new redump/Sega - Saturn.dat: "));
+    assert_eq!(diff.added, vec!["Nintendo - Pokemon Mini"]);
+    assert_eq!(diff.removed, vec!["Nintendo - Virtual Boy"]);
+    assert_eq!(
+        diff.changed.keys().collect::<Vec<_>>(),
+        vec!["Nintendo - Game Boy"]
+    );
+    assert_eq!(
+        diff.changed["Nintendo - Game Boy"].added,
+        vec!["Delta (World)"]
+    );
+}
@@ -0,0 +1,1449 @@
+//! Exercises the scrapers end to end against a local stand-in for each source.
+
+mod common;
+
+use atsumare::diff::ReportFormat;
+use atsumare::source::{Authentication, DatSource, FetchOptions, Resume, Validators};
+use atsumare::state::{parse_dat_version, Partial, State};
+use atsumare::{
+    do_download, download_source, download_source_with, nointro, redump, resume_download, tosec,
+    Credentials, SyncEvent, SyncOptions,
+};
+use common::{output_dir, zip_of, EMPTY_ZIP};
+use hyper::header::{
+    CONTENT_DISPOSITION, CONTENT_RANGE, CONTENT_TYPE, COOKIE, ETAG, IF_NONE_MATCH, IF_RANGE,
+    LOCATION, RANGE, SET_COOKIE,
+};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use regex::Regex;
+use std::cell::RefCell;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io::{Cursor, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use zip::write::{FileOptions, ZipWriter};
+use zip::CompressionMethod;
+
+const BIOS_DAT: &str = r#"clrmamepro (
+	name "Sony - PlayStation - BIOS Images"
+	description "Sony - PlayStation - BIOS Images (1) (2020-01-01 00-00-00)"
+	category "Console"
+	version "2020-01-01 00-00-00"
+	author "redump.org"
+)
+
+game (
+	name "PS-X BIOS (v1.0) (Kernel Version 1.0 A)"
+	description "PS-X BIOS (v1.0) (Kernel Version 1.0 A)"
+	serial "SCPH-1001"
+	version "1.0"
+	rom ( name "SCPH-1001.bin" size 524288 crc 37157331 md5 924e392ed05558ffdb115408c263dccf sha1 10155d8d6e6e832d6ea66db9bc098321fb5e8ebf )
+)
+"#;
+
+/// The daily pack page of DAT-o-Matic.
+const NOINTRO_DAILY_PAGE: &str = include_str!("fixtures/datomatic-daily.html");
+
+fn spawn_server<F, R>(handler: F) -> String
+where
+    F: Fn(Request<Body>) -> R + Clone + Send + Sync + 'static,
+    R: Future<Output = Result<Response<Body>, Infallible>> + Send + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let handler = handler.clone();
+        async move { Ok::<_, Infallible>(service_fn(handler)) }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+    let root = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    root
+}
+
+fn credentials(password: &str) -> Option<Credentials> {
+    Some(Credentials {
+        username: String::from("user"),
+        password: String::from(password),
+    })
+}
+
+fn has_cookie(req: &Request<Body>, cookie: &str) -> bool {
+    req.headers()
+        .get(COOKIE)
+        .and_then(|c| c.to_str().ok())
+        .map(|c| c.contains(cookie))
+        .unwrap_or(false)
+}
+
+fn attachment(content_type: &str, filename: &str, body: impl Into<Body>) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(body.into())
+        .unwrap()
+}
+
+fn redirect(location: &str, cookie: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header(LOCATION, location)
+        .header(SET_COOKIE, cookie)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Responds with 304 Not Modified if the request already has `etag`, or tags the response with it.
+fn tagged(req_etag: Option<&str>, etag: &str, response: Response<Body>) -> Response<Body> {
+    if req_etag == Some(etag) {
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(ETAG, etag)
+            .body(Body::empty())
+            .unwrap();
+    }
+    let (mut parts, body) = response.into_parts();
+    parts.headers.insert(ETAG, etag.parse().unwrap());
+    Response::from_parts(parts, body)
+}
+
+/// Serves the part of `body` the request asks for, if it still has the given `etag`.
+fn ranged(
+    req: &Request<Body>,
+    etag: &str,
+    response: Response<Body>,
+    body: &[u8],
+) -> Response<Body> {
+    let header = |name| req.headers().get(name).and_then(|h| h.to_str().ok());
+    let start = header(RANGE)
+        .and_then(|r| r.strip_prefix("bytes="))
+        .and_then(|r| r.strip_suffix('-'))
+        .and_then(|r| r.parse::<usize>().ok())
+        .filter(|_| header(IF_RANGE) == Some(etag));
+    match start {
+        Some(start) if start < body.len() => {
+            let (mut parts, _) = response.into_parts();
+            parts.status = StatusCode::PARTIAL_CONTENT;
+            let range = format!("bytes {}-{}/{}", start, body.len() - 1, body.len());
+            parts.headers.insert(CONTENT_RANGE, range.parse().unwrap());
+            Response::from_parts(parts, Body::from(body[start..].to_vec()))
+        }
+        _ => response,
+    }
+}
+
+fn not_found() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Spawns a Redump server, returning its root along with the number of requests for its GDI pack,
+/// which is corrupted on the first request.
+fn spawn_redump_server() -> (String, Arc<AtomicUsize>) {
+    let gdi_requests = Arc::new(AtomicUsize::new(0));
+    let counter = gdi_requests.clone();
+    let root = spawn_server(move |req| redump_server(req, counter.clone()));
+    (root, gdi_requests)
+}
+
+async fn redump_server(
+    req: Request<Body>,
+    gdi_requests: Arc<AtomicUsize>,
+) -> Result<Response<Body>, Infallible> {
+    let logged_in = has_cookie(&req, "redump_cookie=session");
+    let etag = req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|e| e.to_str().ok())
+        .map(|e| e.to_owned());
+    let etag = etag.as_deref();
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/login/") => Response::builder()
+            .header(SET_COOKIE, "PHPSESSID=login")
+            .body(Body::from(
+                r#"<form><input type="hidden" name="csrf_token" value="deadbeef" /></form>"#,
+            ))
+            .unwrap(),
+        (&Method::POST, "/login/") => {
+            let has_session = has_cookie(&req, "PHPSESSID=login");
+            let form = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let form = String::from_utf8_lossy(&form);
+            if has_session
+                && form.contains("csrf_token=deadbeef")
+                && form.contains("req_username=user")
+                && form.contains("req_password=hunter2")
+            {
+                redirect("/", "redump_cookie=session")
+            } else {
+                Response::new(Body::from("Wrong username or password."))
+            }
+        }
+        (&Method::GET, "/downloads/") => {
+            let private = if logged_in {
+                r#"<tr><td>Microsoft - Xbox</td><td><a href="/datfile/xbox/">Datfile</a></td></tr>"#
+            } else {
+                ""
+            };
+            let downloads_etag = if logged_in {
+                "\"downloads-private\""
+            } else {
+                "\"downloads\""
+            };
+            let page = Response::new(Body::from(format!(
+                r#"<table class="statistics"><tbody>
+                <tr><th>System</th><th>Datfile</th><th>Cuesheets</th></tr>
+                <tr><td>Sony - PlayStation</td><td><a href="/datfile/psx/">Datfile</a></td><td><a href="/cues/psx/">Cuesheets</a></td><td><a href="/sbi/psx/">SBI</a></td><td><a href="/gdi/psx/">GDI</a></td><td><a href="/keys/psx/">Keys</a></td></tr>
+                <tr><td>Sony - PlayStation - BIOS Images</td><td><a href="/datfile/psx-bios/">Datfile</a></td><td><a href="/cues/psx-bios/">Cuesheets</a></td></tr>
+                {}
+                </tbody></table>"#,
+                private
+            )));
+            tagged(etag, downloads_etag, page)
+        }
+        (&Method::GET, "/datfile/psx/serial,version") => tagged(
+            etag,
+            "\"psx-1\"",
+            ranged(
+                &req,
+                "\"psx-1\"",
+                attachment(
+                    "application/zip",
+                    "Sony - PlayStation - Datfile (1) (2020-01-01 00-00-00).zip",
+                    EMPTY_ZIP,
+                ),
+                EMPTY_ZIP,
+            ),
+        ),
+        (&Method::GET, "/datfile/xbox/serial,version") if logged_in => attachment(
+            "application/zip",
+            "Microsoft - Xbox - Datfile (1) (2020-01-01 00-00-00).zip",
+            EMPTY_ZIP,
+        ),
+        (&Method::GET, "/cues/psx/") => attachment(
+            "application/zip",
+            "Sony - PlayStation - Cuesheets (1) (2020-01-01 00-00-00).zip",
+            EMPTY_ZIP,
+        ),
+        (&Method::GET, "/cues/psx-bios/") if logged_in => attachment(
+            "application/zip",
+            "Sony - PlayStation - BIOS Images - Cuesheets (1) (2020-01-01 00-00-00).zip",
+            EMPTY_ZIP,
+        ),
+        (&Method::GET, "/cues/psx-bios/") => Response::builder()
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Body::from("<html><body>Please log in.</body></html>"))
+            .unwrap(),
+        (&Method::GET, "/sbi/psx/") => attachment(
+            "application/zip",
+            "Sony - PlayStation - SBI Subchannels (1) (2020-01-01 00-00-00).zip",
+            "<!DOCTYPE html><html><body>Database error</body></html>",
+        ),
+        (&Method::GET, "/gdi/psx/") => {
+            // The first download arrives corrupted.
+            let mut gdi = ZipWriter::new(Cursor::new(Vec::new()));
+            let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+            gdi.start_file("Sony - PlayStation.gdi", stored).unwrap();
+            gdi.write_all(b"1 0 4 2352 track01.bin 0").unwrap();
+            let mut gdi = gdi.finish().unwrap().into_inner();
+            if gdi_requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                let track = gdi.windows(5).position(|w| w == b"track").unwrap();
+                gdi[track] = b'T';
+            }
+            attachment(
+                "application/zip",
+                "Sony - PlayStation - GDI Files (1) (2020-01-01 00-00-00).zip",
+                gdi,
+            )
+        }
+        (&Method::GET, "/keys/psx/") if logged_in => attachment(
+            "application/zip",
+            "Sony - PlayStation - Keys (1) (2020-01-01 00-00-00).zip",
+            EMPTY_ZIP,
+        ),
+        (&Method::GET, "/datfile/psx-bios/serial,version") => attachment(
+            "application/x-ms-download; charset=ISO-8859-1",
+            "Sony - PlayStation - BIOS Images (1) (2020-01-01 00-00-00).dat",
+            BIOS_DAT,
+        ),
+        _ => not_found(),
+    };
+    Ok(response)
+}
+
+async fn nointro_server(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let logged_in = has_cookie(&req, "PHPSESSID=session");
+    let prepared = has_cookie(&req, "PHPSESSID=prepared");
+    let etag = req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|e| e.to_str().ok())
+        .map(|e| e.to_owned());
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let query = req.uri().query().unwrap_or("").to_owned();
+    let form = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let form = String::from_utf8_lossy(&form);
+
+    // The stand-in numbers the sections of daily packs by the checkboxes of the daily page.
+    let sections = [
+        "no-intro",
+        "non-game",
+        "unofficial",
+        "non-redump",
+        "aftermarket",
+        "source-code",
+    ]
+    .iter()
+    .enumerate()
+    .filter(|(_, value)| form.contains(&format!("section%5B%5D={}", value)))
+    .fold(0, |sections, (i, _)| sections | 1 << i);
+    let system = query
+        .strip_prefix("page=download&op=dat&s=")
+        .and_then(|s| s.parse::<u32>().ok());
+    let download = query
+        .strip_prefix("page=manager&download=")
+        .and_then(|d| d.parse::<u32>().ok());
+
+    let response = match (method, path.as_str(), query.as_str()) {
+        (Method::POST, "/", "") => {
+            if form.contains("username=user") && form.contains("password=hunter2") {
+                redirect("index.php?page=main", "PHPSESSID=session")
+            } else {
+                redirect("index.php?page=message", "PHPSESSID=anonymous")
+            }
+        }
+        (Method::GET, "/", "page=download&op=daily") => {
+            Response::new(Body::from(NOINTRO_DAILY_PAGE))
+        }
+        (Method::POST, "/", "page=download&op=daily") => {
+            // Downloads are numbered by section, then 1 and 2 for public and private standard packs,
+            // then 3 and 4 for public and private Parent/Clone packs.
+            let offset = if form.contains("dat_type=xml") { 2 } else { 0 };
+            let private = form.contains("private=Ok");
+            let download = sections * 10 + offset + if private { 2 } else { 1 };
+            if !form.contains("daily_download=Prepare") || sections == 0 || (private && !logged_in)
+            {
+                not_found()
+            } else {
+                redirect(
+                    &format!("index.php?page=manager&download={}", download),
+                    "PHPSESSID=prepared",
+                )
+            }
+        }
+        (Method::GET, "/", "page=download&op=dat") => Response::new(Body::from(
+            r#"<form><select name="sel_s">
+            <option value="">Select a system</option>
+            <optgroup label="No-Intro">
+            <option value="46">Nintendo - Game Boy</option>
+            <option value="23">Nintendo - Game Boy Advance</option>
+            </optgroup>
+            <optgroup label="Non-Game">
+            <option value="158">Nintendo - Game Boy Advance (e-Reader)</option>
+            </optgroup>
+            </select></form>"#,
+        )),
+        (Method::POST, "/", _) if system.is_some() => {
+            // System downloads are numbered from 1000 by system ID.
+            if !form.contains("download=Prepare") || form.contains("daily_download") {
+                not_found()
+            } else {
+                redirect(
+                    &format!("index.php?page=manager&download={}", 1000 + system.unwrap()),
+                    "PHPSESSID=prepared",
+                )
+            }
+        }
+        (Method::POST, "/index.php", _) if prepared && download.is_some() => {
+            let download = download.unwrap();
+            let filename = match download {
+                1046 => "Nintendo - Game Boy (20200101-000000).zip",
+                1023 => "Nintendo - Game Boy Advance (20200101-000000).zip",
+                1158 => "Nintendo - Game Boy Advance (e-Reader) (20200101-000000).zip",
+                d if d % 10 == 1 || d % 10 == 3 => "No-Intro Love Pack (Standard) (2020-01-01).zip",
+                _ => "No-Intro Love Pack (Private) (2020-01-01).zip",
+            };
+            let response = attachment("application/zip", filename, EMPTY_ZIP);
+            tagged(etag.as_deref(), &format!("\"{}\"", download), response)
+        }
+        _ => not_found(),
+    };
+    Ok(response)
+}
+
+async fn tosec_server(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let query = req.uri().query().unwrap_or("");
+    let response = match (req.method(), req.uri().path(), query) {
+        (&Method::GET, "/downloads/category/22-datfiles", _) => Response::new(Body::from(
+            r#"<ul>
+            <li><a href="/downloads/category/49-2020-01-01">2020-01-01</a></li>
+            <li><a href="/downloads/category/50-2020-07-29">2020-07-29</a></li>
+            <li><a href="/downloads/category/12-tools">Tools</a></li>
+            </ul>"#,
+        )),
+        (&Method::GET, "/downloads/category/49-2020-01-01", "") => Response::new(Body::from(
+            r#"<a href="/downloads/category/49-2020-01-01?download=98:tosec-dat-pack-complete-3000-tosec-v2020-01-01">Download</a>"#,
+        )),
+        (&Method::GET, "/downloads/category/50-2020-07-29", "") => Response::new(Body::from(
+            r#"<a href="/downloads/category/50-2020-07-29?download=97:tosec-release-notes">Notes</a>
+            <a href="/downloads/category/50-2020-07-29?download=99:tosec-dat-pack-complete-3036-tosec-v2020-07-29">Download</a>"#,
+        )),
+        (
+            &Method::GET,
+            "/downloads/category/49-2020-01-01",
+            "download=98:tosec-dat-pack-complete-3000-tosec-v2020-01-01",
+        ) => attachment(
+            "application/zip",
+            "TOSEC - DAT Pack - Complete (3000) (TOSEC-v2020-01-01).zip",
+            EMPTY_ZIP,
+        ),
+        (
+            &Method::GET,
+            "/downloads/category/50-2020-07-29",
+            "download=99:tosec-dat-pack-complete-3036-tosec-v2020-07-29",
+        ) => attachment(
+            "application/zip",
+            "TOSEC - DAT Pack - Complete (3036) (TOSEC-v2020-07-29).zip",
+            zip_of(&[
+                ("TOSEC/Atari 2600 - Games (TOSEC-v2020-07-29).dat", "tosec"),
+                (
+                    "TOSEC-ISO/Sega Saturn - Games (TOSEC-v2020-07-29).dat",
+                    "iso",
+                ),
+                (
+                    "TOSEC-PIX/Atari 2600 - Manuals (TOSEC-v2020-07-29).dat",
+                    "pix",
+                ),
+            ]),
+        ),
+        _ => not_found(),
+    };
+    Ok(response)
+}
+
+#[tokio::test]
+async fn redump_login_and_download() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default()
+        .with_root(&root)
+        .with_forum(&root);
+    let mut source = redump::Redump::new(credentials("hunter2")).with_endpoints(endpoints);
+
+    let dir = output_dir("redump");
+    download_source(&mut source, &dir).await.unwrap();
+
+    assert!(dir
+        .join("Sony - PlayStation - Datfile (1) (2020-01-01 00-00-00).zip")
+        .exists());
+    assert!(dir
+        .join("Microsoft - Xbox - Datfile (1) (2020-01-01 00-00-00).zip")
+        .exists());
+
+    let bios = std::fs::read_to_string(
+        dir.join("Sony - PlayStation - BIOS Images (1) (2020-01-01 00-00-00).dat"),
+    )
+    .unwrap();
+    assert!(bios.contains(r#"<game name="[BIOS] PS-X BIOS (USA) (v1.0) (Version 1.0 A)">"#));
+    assert!(bios.contains(r#"crc="37157331""#));
+    // The serial and version requested with the DAT survive the conversion to XML.
+    assert!(bios.contains("<serial>SCPH-1001</serial>"));
+    assert!(bios.contains("<version>1.0</version>"));
+    assert_eq!(
+        parse_dat_version(&bios).as_deref(),
+        Some("2020-01-01 00-00-00")
+    );
+}
+
+#[tokio::test]
+async fn redump_rejects_bad_credentials() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default()
+        .with_root(&root)
+        .with_forum(&root);
+    let mut source = redump::Redump::new(credentials("wrong")).with_endpoints(endpoints);
+
+    assert!(matches!(
+        source.authenticate().await.unwrap(),
+        Authentication::InvalidCredentials
+    ));
+    // Private DATs are not listed without a session.
+    assert_eq!(source.artifacts().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn redump_lists_systems() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default().with_root(&root);
+
+    let systems = redump::fetch_systems(&endpoints, &None::<String>)
+        .await
+        .unwrap();
+    let systems = systems
+        .iter()
+        .map(|s| (s.slug.as_str(), s.name.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        systems,
+        vec![
+            ("psx", "Sony - PlayStation"),
+            ("psx-bios", "Sony - PlayStation - BIOS Images"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn redump_filters_systems() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default()
+        .with_root(&root)
+        .with_forum(&root);
+
+    let source = redump::Redump::new(None)
+        .with_endpoints(endpoints.clone())
+        .with_filter(redump::SystemFilter {
+            include: vec![String::from("psx")],
+            ..Default::default()
+        });
+    let artifacts = source.artifacts().await.unwrap();
+    assert_eq!(artifacts.len(), 1);
+    assert!(artifacts[0].url.ends_with("/datfile/psx/serial,version"));
+
+    let source = redump::Redump::new(None)
+        .with_endpoints(endpoints)
+        .with_filter(redump::SystemFilter {
+            exclude_names: vec![Regex::new("BIOS").unwrap()],
+            ..Default::default()
+        });
+    let artifacts = source.artifacts().await.unwrap();
+    assert_eq!(artifacts.len(), 1);
+    assert!(artifacts[0].url.ends_with("/datfile/psx/serial,version"));
+}
+
+#[tokio::test]
+async fn redump_variants() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default().with_root(&root);
+
+    let mut variants = redump::Variants {
+        default: redump::DatVariant::Serial,
+        ..Default::default()
+    };
+    variants
+        .systems
+        .insert(String::from("psx-bios"), redump::DatVariant::Plain);
+
+    let source = redump::Redump::new(None)
+        .with_endpoints(endpoints)
+        .with_variants(variants);
+    let artifacts = source.artifacts().await.unwrap();
+    let urls = artifacts
+        .iter()
+        .map(|a| (a.id.as_str(), a.url.trim_start_matches(&root)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        urls,
+        vec![
+            ("datfile/psx", "/datfile/psx/serial"),
+            ("datfile/psx-bios", "/datfile/psx-bios/"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn redump_packs_by_kind() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default()
+        .with_root(&root)
+        .with_forum(&root);
+    let kinds = vec![redump::PackKind::Cuesheets, redump::PackKind::Keys];
+
+    // Keys, and packs redump.org turns out to serve only to logged in users, are skipped without logging in.
+    let mut source = redump::Redump::new(None)
+        .with_endpoints(endpoints.clone())
+        .with_kinds(kinds.clone());
+    let dir = output_dir("redump-packs-anonymous");
+    download_source(&mut source, &dir).await.unwrap();
+    assert!(dir
+        .join("cues/Sony - PlayStation - Cuesheets (1) (2020-01-01 00-00-00).zip")
+        .exists());
+    assert!(!dir.join("keys").exists());
+    assert!(!dir
+        .join("cues/Sony - PlayStation - BIOS Images - Cuesheets (1) (2020-01-01 00-00-00).zip")
+        .exists());
+    assert!(!dir
+        .join("Sony - PlayStation - Datfile (1) (2020-01-01 00-00-00).zip")
+        .exists());
+
+    let mut source = redump::Redump::new(credentials("hunter2"))
+        .with_endpoints(endpoints)
+        .with_kinds(kinds);
+    let dir = output_dir("redump-packs");
+    download_source(&mut source, &dir).await.unwrap();
+    assert!(dir
+        .join("cues/Sony - PlayStation - Cuesheets (1) (2020-01-01 00-00-00).zip")
+        .exists());
+    assert!(dir
+        .join("keys/Sony - PlayStation - Keys (1) (2020-01-01 00-00-00).zip")
+        .exists());
+    assert!(dir
+        .join("cues/Sony - PlayStation - BIOS Images - Cuesheets (1) (2020-01-01 00-00-00).zip")
+        .exists());
+}
+
+#[tokio::test]
+async fn nointro_prepare_and_download() {
+    let root = spawn_server(nointro_server);
+    let endpoints = nointro::Endpoints::default().with_root(&root);
+    let mut source = nointro::NoIntro::new(credentials("hunter2")).with_endpoints(endpoints);
+
+    assert!(matches!(
+        source.authenticate().await.unwrap(),
+        Authentication::LoggedIn(ref user) if user == "user"
+    ));
+
+    let artifacts = source.artifacts().await.unwrap();
+    assert_eq!(artifacts.len(), 2);
+
+    // Fetch directly rather than through download_source to skip the throttling delay.
+    let dir = output_dir("nointro");
+    for artifact in artifacts {
+        let download = source.fetch(&artifact).await.unwrap();
+        do_download(&dir, &download.filename, download.stream, |_| {})
+            .await
+            .unwrap();
+    }
+
+    assert!(dir
+        .join("No-Intro Love Pack (Standard) (2020-01-01).zip")
+        .exists());
+    assert!(dir
+        .join("No-Intro Love Pack (Private) (2020-01-01).zip")
+        .exists());
+}
+
+#[tokio::test]
+async fn nointro_parent_clone_packs() {
+    let root = spawn_server(nointro_server);
+    let endpoints = nointro::Endpoints::default().with_root(&root);
+    let source = nointro::NoIntro::new(None)
+        .with_endpoints(endpoints)
+        .with_dat_types(vec![
+            nointro::DatType::Standard,
+            nointro::DatType::ParentClone,
+        ]);
+
+    let artifacts = source.artifacts().await.unwrap();
+    let ids = artifacts.iter().map(|a| a.id.as_str()).collect::<Vec<_>>();
+    assert_eq!(ids, vec!["daily", "daily-parent-clone"]);
+
+    let dir = output_dir("nointro-parent-clone");
+    for artifact in artifacts {
+        let download = source.fetch(&artifact).await.unwrap();
+        do_download(&dir, &download.filename, download.stream, |_| {})
+            .await
+            .unwrap();
+    }
+
+    assert!(dir
+        .join("No-Intro Love Pack (Standard) (2020-01-01).zip")
+        .exists());
+    assert!(dir
+        .join("No-Intro Love Pack (Standard) (2020-01-01) (Parent-Clone).zip")
+        .exists());
+}
+
+#[tokio::test]
+async fn nointro_sections() {
+    let root = spawn_server(nointro_server);
+    let endpoints = nointro::Endpoints::default().with_root(&root);
+    let source = nointro::NoIntro::new(None)
+        .with_endpoints(endpoints)
+        .with_sections(vec![
+            vec![nointro::Section::NonGame],
+            vec![nointro::Section::NoIntro, nointro::Section::Aftermarket],
+        ]);
+
+    let artifacts = source.artifacts().await.unwrap();
+    let ids = artifacts
+        .iter()
+        .map(|a| (a.id.as_str(), a.url.trim_start_matches(&root)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ids,
+        vec![
+            ("daily-nongame", "/?page=download&op=daily"),
+            ("daily-nointro+aftermarket", "/?page=download&op=daily"),
+        ]
+    );
+
+    let dir = output_dir("nointro-sections");
+    for artifact in artifacts {
+        let download = source.fetch(&artifact).await.unwrap();
+        do_download(&dir, &download.filename, download.stream, |_| {})
+            .await
+            .unwrap();
+    }
+
+    assert!(dir
+        .join("No-Intro Love Pack (Standard) (2020-01-01) (Non-Game).zip")
+        .exists());
+    assert!(dir
+        .join("No-Intro Love Pack (Standard) (2020-01-01) (No-Intro + Aftermarket).zip")
+        .exists());
+}
+
+#[tokio::test]
+async fn nointro_lists_systems() {
+    let root = spawn_server(nointro_server);
+    let endpoints = nointro::Endpoints::default().with_root(&root);
+
+    let systems = nointro::fetch_systems(&endpoints, &None).await.unwrap();
+    let systems = systems
+        .iter()
+        .map(|s| (s.id, s.name.as_str(), s.section.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        systems,
+        vec![
+            (46, "Nintendo - Game Boy", Some(nointro::Section::NoIntro)),
+            (
+                23,
+                "Nintendo - Game Boy Advance",
+                Some(nointro::Section::NoIntro)
+            ),
+            (
+                158,
+                "Nintendo - Game Boy Advance (e-Reader)",
+                Some(nointro::Section::NonGame)
+            )
+        ]
+    );
+}
+
+#[tokio::test]
+async fn nointro_system_dats() {
+    let root = spawn_server(nointro_server);
+    let endpoints = nointro::Endpoints::default().with_root(&root);
+    let source = nointro::NoIntro::new(None)
+        .with_endpoints(endpoints)
+        .with_sections(vec![])
+        .with_systems(vec![46, 23, 158])
+        .with_dat_types(vec![
+            nointro::DatType::Standard,
+            nointro::DatType::ParentClone,
+        ]);
+
+    let artifacts = source.artifacts().await.unwrap();
+    let ids = artifacts
+        .iter()
+        .map(|a| {
+            (
+                a.id.as_str(),
+                a.url.trim_start_matches(&root),
+                a.collection.as_deref().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ids,
+        vec![
+            ("system-46", "/?page=download&op=dat&s=46", "No-Intro"),
+            (
+                "system-46-parent-clone",
+                "/?page=download&op=dat&s=46",
+                "No-Intro (Parent-Clone)"
+            ),
+            ("system-23", "/?page=download&op=dat&s=23", "No-Intro"),
+            (
+                "system-23-parent-clone",
+                "/?page=download&op=dat&s=23",
+                "No-Intro (Parent-Clone)"
+            ),
+            ("system-158", "/?page=download&op=dat&s=158", "Non-Game"),
+            (
+                "system-158-parent-clone",
+                "/?page=download&op=dat&s=158",
+                "Non-Game (Parent-Clone)"
+            ),
+        ]
+    );
+
+    let dir = output_dir("nointro-systems");
+    for artifact in artifacts {
+        let download = source.fetch(&artifact).await.unwrap();
+        do_download(&dir, &download.filename, download.stream, |_| {})
+            .await
+            .unwrap();
+    }
+
+    assert!(dir
+        .join("Nintendo - Game Boy (20200101-000000).zip")
+        .exists());
+    assert!(dir
+        .join("Nintendo - Game Boy (20200101-000000) (Parent-Clone).zip")
+        .exists());
+    assert!(dir
+        .join("Nintendo - Game Boy Advance (20200101-000000).zip")
+        .exists());
+    assert!(dir
+        .join("Nintendo - Game Boy Advance (e-Reader) (20200101-000000).zip")
+        .exists());
+}
+
+#[tokio::test]
+async fn nointro_requests_conditionally() {
+    let root = spawn_server(nointro_server);
+    let endpoints = nointro::Endpoints::default().with_root(&root);
+    let source = nointro::NoIntro::new(None)
+        .with_endpoints(endpoints)
+        .with_sections(Vec::new())
+        .with_systems(vec![46]);
+    let artifacts = source.artifacts().await.unwrap();
+
+    let download = source.fetch(&artifacts[0]).await.unwrap();
+    assert_eq!(download.validators.etag.as_deref(), Some("\"1046\""));
+
+    // Fetching through the trait sends the saved validators, so an unchanged DAT is not downloaded again.
+    let options = FetchOptions {
+        if_modified: Some(download.validators),
+        resume: None,
+    };
+    assert!(source
+        .fetch_with(&artifacts[0], &options)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn nointro_rejects_unknown_systems() {
+    let root = spawn_server(nointro_server);
+    let endpoints = nointro::Endpoints::default().with_root(&root);
+    let source = nointro::NoIntro::new(None)
+        .with_endpoints(endpoints)
+        .with_systems(vec![99]);
+
+    assert!(source.artifacts().await.is_err());
+}
+
+#[tokio::test]
+async fn nointro_rejects_bad_credentials() {
+    let root = spawn_server(nointro_server);
+    let endpoints = nointro::Endpoints::default().with_root(&root);
+    let mut source = nointro::NoIntro::new(credentials("wrong")).with_endpoints(endpoints);
+
+    assert!(matches!(
+        source.authenticate().await.unwrap(),
+        Authentication::InvalidCredentials
+    ));
+    assert_eq!(source.artifacts().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn tosec_downloads_latest_release() {
+    let root = spawn_server(tosec_server);
+    let mut source =
+        tosec::Tosec::new().with_endpoints(tosec::Endpoints::default().with_root(&root));
+
+    let artifacts = source.artifacts().await.unwrap();
+    assert_eq!(artifacts.len(), 1);
+    assert_eq!(artifacts[0].version.as_deref(), Some("2020-07-29"));
+
+    let dir = output_dir("tosec");
+    download_source(&mut source, &dir).await.unwrap();
+
+    assert!(dir
+        .join("TOSEC - DAT Pack - Complete (3036) (TOSEC-v2020-07-29).zip")
+        .exists());
+}
+
+#[tokio::test]
+async fn tosec_pinned_release() {
+    let root = spawn_server(tosec_server);
+    let endpoints = tosec::Endpoints::default().with_root(&root);
+
+    let source = tosec::Tosec::new()
+        .with_endpoints(endpoints.clone())
+        .with_release("2020-01-01");
+    let artifacts = source.artifacts().await.unwrap();
+    assert_eq!(artifacts[0].version.as_deref(), Some("2020-01-01"));
+    assert!(artifacts[0]
+        .url
+        .ends_with("tosec-dat-pack-complete-3000-tosec-v2020-01-01"));
+
+    let missing = tosec::Tosec::new()
+        .with_endpoints(endpoints)
+        .with_release("1999-01-01");
+    assert!(missing.artifacts().await.is_err());
+}
+
+#[tokio::test]
+async fn tosec_discovery_fails_loudly() {
+    let root = spawn_server(tosec_server);
+    let source = tosec::Tosec::new().with_endpoints(tosec::Endpoints {
+        datfiles: format!("{}/downloads/category/12-tools", root),
+    });
+    assert!(source.artifacts().await.is_err());
+}
+
+#[tokio::test]
+async fn tosec_extracts_selected_collections() {
+    let root = spawn_server(tosec_server);
+    let mut source = tosec::Tosec::new()
+        .with_endpoints(tosec::Endpoints::default().with_root(&root))
+        .with_collections(vec![tosec::Collection::TosecIso]);
+
+    let dir = output_dir("tosec-collections");
+    download_source(&mut source, &dir).await.unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(dir.join("TOSEC-ISO/Sega Saturn - Games (TOSEC-v2020-07-29).dat"))
+            .unwrap(),
+        "iso"
+    );
+    assert!(!dir.join("TOSEC").exists());
+    assert!(!dir.join("TOSEC-PIX").exists());
+    assert!(dir
+        .join("TOSEC - DAT Pack - Complete (3036) (TOSEC-v2020-07-29).zip")
+        .exists());
+
+    // As with organizing, the pack is only deleted when asked to.
+    let dir = output_dir("tosec-collections-delete");
+    let options = SyncOptions {
+        keep_archives: false,
+        ..SyncOptions::default()
+    };
+    download_source_with(&mut source, &dir, &options, &|_| {})
+        .await
+        .unwrap();
+    assert!(dir
+        .join("TOSEC-ISO/Sega Saturn - Games (TOSEC-v2020-07-29).dat")
+        .exists());
+    assert!(!dir
+        .join("TOSEC - DAT Pack - Complete (3036) (TOSEC-v2020-07-29).zip")
+        .exists());
+}
+
+#[tokio::test]
+async fn tosec_extracts_newly_selected_collections() {
+    let root = spawn_server(tosec_server);
+    let endpoints = tosec::Endpoints::default().with_root(&root);
+    let dir = output_dir("tosec-reselect");
+
+    let mut source = tosec::Tosec::new()
+        .with_endpoints(endpoints.clone())
+        .with_collections(vec![tosec::Collection::TosecIso]);
+    download_source(&mut source, &dir).await.unwrap();
+    assert!(!dir.join("TOSEC").exists());
+
+    let mut source = tosec::Tosec::new()
+        .with_endpoints(endpoints.clone())
+        .with_collections(vec![tosec::Collection::Tosec, tosec::Collection::TosecIso]);
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert!(report.up_to_date.is_empty());
+    assert!(dir.join("TOSEC").exists());
+    assert!(dir.join("TOSEC-ISO").exists());
+
+    let mut source = tosec::Tosec::new()
+        .with_endpoints(endpoints)
+        .with_collections(vec![tosec::Collection::TosecIso, tosec::Collection::Tosec]);
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.up_to_date.len(), 1);
+}
+
+#[tokio::test]
+async fn sync_reports_progress() {
+    let root = spawn_server(tosec_server);
+    let endpoints = tosec::Endpoints::default().with_root(&root);
+    let dir = output_dir("sync-events");
+    let mut source = tosec::Tosec::new().with_endpoints(endpoints);
+
+    let events = RefCell::new(Vec::new());
+    let on_event = |event| events.borrow_mut().push(event);
+    download_source_with(&mut source, &dir, &SyncOptions::default(), &on_event)
+        .await
+        .unwrap();
+    let events = events.take();
+    assert!(matches!(events[0], SyncEvent::Authenticated(Authentication::Unsupported)));
+    assert!(matches!(events[1], SyncEvent::Fetching { ref id, .. } if id == "pack"));
+    assert!(matches!(events[2], SyncEvent::Saving { offset: 0, .. }));
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, SyncEvent::Progress { written, length, .. } if written == length)));
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, SyncEvent::Added { ref id, .. } if id == "pack")));
+    assert!(matches!(events.last(), Some(SyncEvent::Cataloged(3))));
+
+    let events = RefCell::new(Vec::new());
+    let on_event = |event| events.borrow_mut().push(event);
+    download_source_with(&mut source, &dir, &SyncOptions::default(), &on_event)
+        .await
+        .unwrap();
+    assert!(events
+        .take()
+        .iter()
+        .any(|event| matches!(event, SyncEvent::UpToDate { ref id, .. } if id == "pack")));
+}
+
+#[tokio::test]
+async fn sync_records_state() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default().with_root(&root);
+    let dir = output_dir("sync-state");
+
+    let mut source = redump::Redump::new(None)
+        .with_endpoints(endpoints.clone())
+        .with_filter(redump::SystemFilter {
+            include: vec![String::from("psx-bios")],
+            ..Default::default()
+        });
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.added, vec!["datfile/psx-bios"]);
+
+    let state = State::load(&dir).await.unwrap();
+    let saved = state.get("Redump", "datfile/psx-bios").unwrap();
+    assert!(saved.url.ends_with("/datfile/psx-bios/serial,version"));
+    assert_eq!(
+        saved.path,
+        "Sony - PlayStation - BIOS Images (1) (2020-01-01 00-00-00).dat"
+    );
+    assert_eq!(saved.sha256.len(), 64);
+    assert_eq!(saved.dat_version.as_deref(), Some("2020-01-01 00-00-00"));
+
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert!(report.added.is_empty());
+    assert_eq!(report.unchanged, vec!["datfile/psx-bios"]);
+}
+
+#[tokio::test]
+async fn sync_skips_current_versions() {
+    let root = spawn_server(tosec_server);
+    let endpoints = tosec::Endpoints::default().with_root(&root);
+    let dir = output_dir("sync-skip");
+
+    let mut source = tosec::Tosec::new()
+        .with_endpoints(endpoints.clone())
+        .with_release("2020-01-01");
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.added, vec!["pack"]);
+
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.up_to_date, vec!["pack"]);
+
+    let mut source = tosec::Tosec::new().with_endpoints(endpoints);
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.updated, vec!["pack"]);
+}
+
+#[tokio::test]
+async fn sync_requests_conditionally() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default().with_root(&root);
+    let dir = output_dir("sync-conditional");
+
+    let mut source = redump::Redump::new(None)
+        .with_endpoints(endpoints.clone())
+        .with_filter(redump::SystemFilter {
+            include: vec![String::from("psx")],
+            ..Default::default()
+        });
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.added, vec!["datfile/psx"]);
+
+    let state = State::load(&dir).await.unwrap();
+    let saved = state.get("Redump", "datfile/psx").unwrap();
+    assert_eq!(saved.validators.etag.as_deref(), Some("\"psx-1\""));
+    let page = &state.pages["Redump"][&endpoints.downloads];
+    assert_eq!(page.validators.etag.as_deref(), Some("\"downloads\""));
+
+    // The downloads page comes back empty with 304 Not Modified, so the cached copy must be used.
+    let mut source = redump::Redump::new(None)
+        .with_endpoints(endpoints)
+        .with_filter(redump::SystemFilter {
+            include: vec![String::from("psx")],
+            ..Default::default()
+        });
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.up_to_date, vec!["datfile/psx"]);
+}
+
+#[tokio::test]
+async fn resume_partial_download() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default().with_root(&root);
+    let dir = output_dir("resume");
+
+    let source = redump::Redump::new(None)
+        .with_endpoints(endpoints)
+        .with_filter(redump::SystemFilter {
+            include: vec![String::from("psx")],
+            ..Default::default()
+        });
+    let artifacts = source.artifacts().await.unwrap();
+    let options = FetchOptions {
+        if_modified: None,
+        resume: Some(Resume {
+            offset: 10,
+            validators: Validators {
+                etag: Some(String::from("\"psx-1\"")),
+                last_modified: None,
+            },
+        }),
+    };
+    let download = source
+        .fetch_with(&artifacts[0], &options)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(download.offset, 10);
+    assert_eq!(download.length, EMPTY_ZIP.len() as u64);
+
+    let part = dir.join(format!("{}.part", download.filename));
+    std::fs::write(&part, &EMPTY_ZIP[..10]).unwrap();
+    let size = resume_download(
+        &dir,
+        &download.filename,
+        10,
+        download.length,
+        download.stream,
+        |_| {},
+    )
+    .await
+    .unwrap();
+    assert_eq!(size, EMPTY_ZIP.len() as u64);
+    assert!(!part.exists());
+    assert_eq!(
+        std::fs::read(dir.join(&download.filename)).unwrap(),
+        EMPTY_ZIP
+    );
+}
+
+#[tokio::test]
+async fn resume_restarts_without_range_support() {
+    let root = spawn_server(tosec_server);
+    let endpoints = tosec::Endpoints::default().with_root(&root);
+    let dir = output_dir("resume-restart");
+
+    let filename = "TOSEC - DAT Pack - Complete (3000) (TOSEC-v2020-01-01).zip";
+    std::fs::write(dir.join(format!("{}.part", filename)), "garbage").unwrap();
+    let mut state = State::default();
+    state.set_partial(
+        "TOSEC",
+        "pack",
+        Some(Partial {
+            path: format!("{}.part", filename),
+            validators: Validators {
+                etag: Some(String::from("\"stale\"")),
+                last_modified: None,
+            },
+        }),
+    );
+    state.save(&dir).await.unwrap();
+
+    let mut source = tosec::Tosec::new()
+        .with_endpoints(endpoints)
+        .with_release("2020-01-01");
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.added, vec!["pack"]);
+    assert_eq!(std::fs::read(dir.join(filename)).unwrap(), EMPTY_ZIP);
+    assert!(State::load(&dir).await.unwrap().partials.is_empty());
+}
+
+#[tokio::test]
+async fn restarted_downloads_discard_renamed_partials() {
+    let root = spawn_server(tosec_server);
+    let endpoints = tosec::Endpoints::default().with_root(&root);
+    let dir = output_dir("resume-renamed");
+
+    let stale = "TOSEC - DAT Pack - Complete (2999) (TOSEC-v2019-12-31).zip.part";
+    std::fs::write(dir.join(stale), "garbage").unwrap();
+    let mut state = State::default();
+    state.set_partial(
+        "TOSEC",
+        "pack",
+        Some(Partial {
+            path: String::from(stale),
+            validators: Validators {
+                etag: Some(String::from("\"stale\"")),
+                last_modified: None,
+            },
+        }),
+    );
+    state.save(&dir).await.unwrap();
+
+    let mut source = tosec::Tosec::new()
+        .with_endpoints(endpoints)
+        .with_release("2020-01-01");
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.added, vec!["pack"]);
+    assert!(!dir.join(stale).exists());
+    assert!(State::load(&dir).await.unwrap().partials.is_empty());
+}
+
+#[tokio::test]
+async fn unchanged_downloads_discard_partials() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default().with_root(&root);
+    let dir = output_dir("resume-unchanged");
+    let filter = redump::SystemFilter {
+        include: vec![String::from("psx")],
+        ..Default::default()
+    };
+
+    let mut source = redump::Redump::new(None)
+        .with_endpoints(endpoints.clone())
+        .with_filter(filter.clone());
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.added, vec!["datfile/psx"]);
+
+    let part = "Sony - PlayStation (2020-01-01 00-00-00).zip.part";
+    std::fs::write(dir.join(part), &EMPTY_ZIP[..10]).unwrap();
+    let mut state = State::load(&dir).await.unwrap();
+    state.set_partial(
+        "Redump",
+        "datfile/psx",
+        Some(Partial {
+            path: String::from(part),
+            validators: Validators {
+                etag: Some(String::from("\"psx-1\"")),
+                last_modified: None,
+            },
+        }),
+    );
+    state.save(&dir).await.unwrap();
+
+    let mut source = redump::Redump::new(None)
+        .with_endpoints(endpoints)
+        .with_filter(filter);
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.up_to_date, vec!["datfile/psx"]);
+    assert!(!dir.join(part).exists());
+    assert!(State::load(&dir).await.unwrap().partials.is_empty());
+}
+
+#[tokio::test]
+async fn failed_downloads_keep_previous_copy() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default().with_root(&root);
+    let dir = output_dir("keep-previous");
+
+    let filename = "Sony - PlayStation - SBI Subchannels (1) (2020-01-01 00-00-00).zip";
+    std::fs::create_dir_all(dir.join("sbi")).unwrap();
+    std::fs::write(dir.join("sbi").join(filename), EMPTY_ZIP).unwrap();
+
+    let mut source = redump::Redump::new(None)
+        .with_endpoints(endpoints)
+        .with_filter(redump::SystemFilter {
+            include: vec![String::from("psx")],
+            ..Default::default()
+        })
+        .with_kinds(vec![redump::PackKind::Sbi]);
+    let error = download_source(&mut source, &dir).await.unwrap_err();
+    assert!(error.to_string().contains("HTML page"));
+
+    assert_eq!(
+        std::fs::read(dir.join("sbi").join(filename)).unwrap(),
+        EMPTY_ZIP
+    );
+    assert!(!dir.join("sbi").join(format!("{}.part", filename)).exists());
+}
+
+#[tokio::test]
+async fn corrupt_downloads_are_retried() {
+    let (root, gdi_requests) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default().with_root(&root);
+    let dir = output_dir("retry-corrupt");
+
+    let mut source = redump::Redump::new(None)
+        .with_endpoints(endpoints)
+        .with_filter(redump::SystemFilter {
+            include: vec![String::from("psx")],
+            ..Default::default()
+        })
+        .with_kinds(vec![redump::PackKind::Gdi]);
+    let report = download_source(&mut source, &dir).await.unwrap();
+    assert_eq!(report.added, vec!["gdi/psx"]);
+    assert_eq!(gdi_requests.load(Ordering::SeqCst), 2);
+
+    let archive = dir
+        .join("gdi")
+        .join("Sony - PlayStation - GDI Files (1) (2020-01-01 00-00-00).zip");
+    assert_eq!(atsumare::extract::verify_zip(&archive).unwrap(), 1);
+}
+
+#[tokio::test]
+async fn organize_tosec_collections() {
+    let root = spawn_server(tosec_server);
+    let endpoints = tosec::Endpoints::default().with_root(&root);
+    let dir = output_dir("organize-tosec");
+
+    let mut source = tosec::Tosec::new().with_endpoints(endpoints);
+    let options = SyncOptions {
+        organize: true,
+        keep_archives: false,
+        ..SyncOptions::default()
+    };
+    download_source_with(&mut source, &dir, &options, &|_| {})
+        .await
+        .unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(dir.join("tosec/TOSEC/Atari 2600 - Games.dat")).unwrap(),
+        "tosec"
+    );
+    assert!(dir.join("tosec/TOSEC-ISO/Sega Saturn - Games.dat").exists());
+    assert!(dir
+        .join("tosec/TOSEC-PIX/Atari 2600 - Manuals.dat")
+        .exists());
+    assert!(!dir
+        .join("TOSEC - DAT Pack - Complete (3036) (TOSEC-v2020-07-29).zip")
+        .exists());
+
+    // The organized DATs stand in for the deleted pack on the next run.
+    let report = download_source_with(&mut source, &dir, &options, &|_| {})
+        .await
+        .unwrap();
+    assert_eq!(report.up_to_date, vec!["pack"]);
+}
+
+#[tokio::test]
+async fn organize_redump_datfiles() {
+    let (root, _) = spawn_redump_server();
+    let endpoints = redump::Endpoints::default().with_root(&root);
+    let dir = output_dir("organize-redump");
+
+    let mut source = redump::Redump::new(None).with_endpoints(endpoints);
+    let options = SyncOptions {
+        organize: true,
+        keep_archives: false,
+        ..SyncOptions::default()
+    };
+    download_source_with(&mut source, &dir, &options, &|_| {})
+        .await
+        .unwrap();
+
+    let bios = dir.join("redump/Redump/Sony - PlayStation - BIOS Images.dat");
+    assert!(std::fs::read_to_string(bios)
+        .unwrap()
+        .contains("<datafile>"));
+    // Archives without DATs are kept.
+    assert!(dir
+        .join("Sony - PlayStation - Datfile (1) (2020-01-01 00-00-00).zip")
+        .exists());
+}
+
+#[tokio::test]
+async fn organize_saved_artifacts() {
+    let root = spawn_server(tosec_server);
+    let endpoints = tosec::Endpoints::default().with_root(&root);
+    let dir = output_dir("organize-saved");
+    let pack = dir.join("TOSEC - DAT Pack - Complete (3036) (TOSEC-v2020-07-29).zip");
+
+    let mut source = tosec::Tosec::new().with_endpoints(endpoints);
+    download_source(&mut source, &dir).await.unwrap();
+    assert!(!dir.join("tosec").exists());
+
+    // The pack is up to date, but was never organized, so it is organized in place.
+    let options = SyncOptions {
+        organize: true,
+        keep_archives: false,
+        ..SyncOptions::default()
+    };
+    let report = download_source_with(&mut source, &dir, &options, &|_| {})
+        .await
+        .unwrap();
+    assert_eq!(report.up_to_date, vec!["pack"]);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("tosec/TOSEC/Atari 2600 - Games.dat")).unwrap(),
+        "tosec"
+    );
+    assert!(!pack.exists());
+    let state = State::load(&dir).await.unwrap();
+    assert!(state.get("TOSEC", "pack").unwrap().organized);
+
+    // DATs of systems that left the source are removed from the tree.
+    let stale = dir.join("tosec/TOSEC/Atari 5200 - Games.dat");
+    std::fs::write(&stale, "stale").unwrap();
+    download_source_with(&mut source, &dir, &options, &|_| {})
+        .await
+        .unwrap();
+    assert!(!stale.exists());
+    assert!(dir.join("tosec/TOSEC/Atari 2600 - Games.dat").exists());
+
+    // Saved artifacts that are not modified on the server are organized as well.
+    let (root, _) = spawn_redump_server();
+    let mut source =
+        redump::Redump::new(None).with_endpoints(redump::Endpoints::default().with_root(&root));
+    download_source(&mut source, &dir).await.unwrap();
+    let report = download_source_with(&mut source, &dir, &options, &|_| {})
+        .await
+        .unwrap();
+    assert!(report.up_to_date.contains(&String::from("datfile/psx")));
+    let state = State::load(&dir).await.unwrap();
+    assert!(state.get("Redump", "datfile/psx").unwrap().organized);
+}
+
+#[tokio::test]
+async fn sync_reports_catalog_changes() {
+    let root = spawn_server(tosec_server);
+    let endpoints = tosec::Endpoints::default().with_root(&root);
+    let dir = output_dir("catalog-sync");
+    let mut source = tosec::Tosec::new().with_endpoints(endpoints);
+    let options = SyncOptions {
+        catalog_report: Some(ReportFormat::Markdown),
+        ..SyncOptions::default()
+    };
+
+    let report = download_source_with(&mut source, &dir, &options, &|_| {})
+        .await
+        .unwrap();
+    assert_eq!(report.catalog, None);
+    let mut state = State::load(&dir).await.unwrap();
+    let catalog = state.catalogs["TOSEC"].clone();
+    assert_eq!(
+        catalog.keys().collect::<Vec<_>>(),
+        vec![
+            "TOSEC-ISO/Sega Saturn - Games",
+            "TOSEC-PIX/Atari 2600 - Manuals",
+            "TOSEC/Atari 2600 - Games",
+        ]
+    );
+
+    // The pack is up to date, so its DATs are carried over unchanged.
+    let report = download_source_with(&mut source, &dir, &options, &|_| {})
+        .await
+        .unwrap();
+    assert!(report.catalog.unwrap().is_empty());
+    assert!(!dir.join("reports").exists());
+
+    let mut previous = catalog.clone();
+    previous.remove("TOSEC-PIX/Atari 2600 - Manuals");
+    previous.insert(
+        String::from("TOSEC/Atari 5200 - Games"),
+        String::from("old"),
+    );
+    state.catalogs.insert(String::from("TOSEC"), previous);
+    state.save(&dir).await.unwrap();
+
+    let report = download_source_with(&mut source, &dir, &options, &|_| {})
+        .await
+        .unwrap();
+    let changes = report.catalog.unwrap();
+    assert_eq!(changes.appeared, vec!["TOSEC-PIX/Atari 2600 - Manuals"]);
+    assert_eq!(changes.disappeared, vec!["TOSEC/Atari 5200 - Games"]);
+    let reports = std::fs::read_dir(dir.join("reports/tosec"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(reports.len(), 1);
+    assert!(std::fs::read_to_string(&reports[0])
+        .unwrap()
+        .contains("## Disappeared (1)\n\n- TOSEC/Atari 5200 - Games\n"));
+}
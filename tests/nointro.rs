@@ -0,0 +1,50 @@
+//! Checks the No-Intro options that do not need a stand-in DAT-o-Matic.
+
+use atsumare::nointro::{parse_section_checkboxes, DatType, Section};
+
+#[test]
+fn only_supported_dat_types_parse() {
+    assert_eq!("standard".parse::<DatType>().unwrap(), DatType::Standard);
+    assert_eq!(
+        "parent-clone".parse::<DatType>().unwrap(),
+        DatType::ParentClone
+    );
+    let error = "clrmamepro".parse::<DatType>().unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("expected standard or parent-clone"));
+}
+
+#[test]
+fn daily_page_sections_are_scraped() {
+    let page = include_str!("fixtures/datomatic-daily.html");
+    let checkboxes = parse_section_checkboxes(page);
+    let labels = checkboxes
+        .iter()
+        .map(|checkbox| {
+            (
+                checkbox.name.as_str(),
+                checkbox.value.as_str(),
+                checkbox.label.as_str(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        labels,
+        vec![
+            ("section[]", "no-intro", "No-Intro"),
+            ("section[]", "non-game", "Non-Game"),
+            ("section[]", "unofficial", "Unofficial"),
+            ("section[]", "non-redump", "Non-Redump"),
+            ("section[]", "aftermarket", "Aftermarket"),
+            ("section[]", "source-code", "Source Code"),
+        ]
+    );
+
+    assert_eq!("nongame".parse::<Section>().unwrap(), Section::NonGame);
+    assert_eq!(
+        "Source Code".parse::<Section>().unwrap(),
+        Section::Other(String::from("Source Code"))
+    );
+    assert!("-".parse::<Section>().is_err());
+}
@@ -0,0 +1,67 @@
+//! Checks how downloaded DATs are placed in the organized tree.
+
+mod common;
+
+use atsumare::organize::{system_name, Organizer};
+use common::{output_dir, write_zip};
+
+#[test]
+fn organizer_numbers_collisions() {
+    assert_eq!(
+        system_name("Sony - PlayStation - Datfile (1) (2020-01-01 00-00-00).dat"),
+        "Sony - PlayStation"
+    );
+    assert_eq!(
+        system_name("Nintendo - Game Boy (Parent-Clone) (20200101-000000).dat"),
+        "Nintendo - Game Boy (Parent-Clone)"
+    );
+
+    let dir = output_dir("organize-collisions");
+    let archive = dir.join("pack.zip");
+    write_zip(
+        &archive,
+        &[
+            ("Nintendo - Game Boy (20200101-000000).dat", "first"),
+            ("Nintendo - Game Boy (20200202-000000).dat", "second"),
+            ("README.txt", "not a DAT"),
+        ],
+    );
+
+    let mut organizer = Organizer::new(dir.join("no-intro"));
+    let organized = organizer
+        .organize_zip(&archive, "No-Intro", |_| true)
+        .unwrap();
+    assert_eq!(
+        organized,
+        vec![
+            dir.join("no-intro/No-Intro/Nintendo - Game Boy.dat"),
+            dir.join("no-intro/No-Intro/Nintendo - Game Boy (2).dat"),
+        ]
+    );
+    assert_eq!(std::fs::read_to_string(&organized[1]).unwrap(), "second");
+    assert_eq!(
+        organizer.take_renamed(),
+        vec![(organized[0].clone(), organized[1].clone())]
+    );
+    assert!(organizer.take_renamed().is_empty());
+}
+
+#[test]
+fn organizer_prunes_unclaimed_dats() {
+    let dir = output_dir("organize-prune");
+    let kept = dir.join("tosec/TOSEC/Atari 2600 - Games.dat");
+    let placed = dir.join("tosec/TOSEC/Atari 7800 - Games.dat");
+    let stale = dir.join("tosec/TOSEC/Atari 5200 - Games.dat");
+    let archive = dir.join("Atari 7800 - Games (TOSEC-v2020-07-29).dat");
+    std::fs::create_dir_all(kept.parent().unwrap()).unwrap();
+    for file in &[&kept, &stale, &archive] {
+        std::fs::write(file, "tosec").unwrap();
+    }
+
+    let mut organizer = Organizer::new(dir.join("tosec"));
+    organizer.reserve(&kept);
+    assert_eq!(organizer.organize_dat(&archive, "TOSEC").unwrap(), placed);
+    assert_eq!(organizer.prune().unwrap(), vec![stale.clone()]);
+    assert!(kept.exists() && placed.exists() && archive.exists());
+    assert!(!stale.exists());
+}
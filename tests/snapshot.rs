@@ -0,0 +1,172 @@
+//! Checks taking, viewing and pruning snapshots of the organized DATs.
+
+mod common;
+
+use atsumare::snapshot::{
+    list_snapshots, prune_snapshots, take_snapshot, update_latest, Retention,
+};
+use chrono::{Duration, TimeZone, Utc};
+use common::{output_dir, EMPTY_ZIP};
+use std::path::PathBuf;
+
+#[test]
+fn snapshots_link_unchanged_files() {
+    let dir = output_dir("snapshots");
+    std::fs::create_dir_all(dir.join("redump/Redump")).unwrap();
+    std::fs::create_dir_all(dir.join("reports/redump")).unwrap();
+    std::fs::create_dir_all(dir.join("cues")).unwrap();
+    std::fs::write(dir.join("redump/Redump/Sony - PlayStation.dat"), "psx 1").unwrap();
+    std::fs::write(dir.join("redump/Redump/Sega - Saturn.dat"), "ss 1").unwrap();
+    std::fs::write(dir.join("reports/redump/2020-01-01T000000Z.txt"), "report").unwrap();
+    std::fs::write(dir.join("cues/Sony - PlayStation.zip"), EMPTY_ZIP).unwrap();
+    std::fs::write(dir.join("pack.zip"), EMPTY_ZIP).unwrap();
+    std::fs::write(dir.join("pack.zip.part"), "partial").unwrap();
+    std::fs::write(dir.join(".atsumare-state.json"), "{}").unwrap();
+
+    let first = take_snapshot(&dir, Utc.ymd(2020, 1, 1).and_hms(0, 0, 0))
+        .unwrap()
+        .unwrap();
+    update_latest(&dir, &first).unwrap();
+    assert!(first.path.ends_with("snapshots/2020-01-01T000000Z"));
+    assert!(first
+        .path
+        .join("redump/Redump/Sony - PlayStation.dat")
+        .exists());
+    for skipped in &[
+        "reports",
+        "cues",
+        "pack.zip",
+        "pack.zip.part",
+        ".atsumare-state.json",
+    ] {
+        assert!(
+            !first.path.join(skipped).exists(),
+            "{} was snapshotted",
+            skipped
+        );
+    }
+
+    // Nothing changed, so no snapshot is taken.
+    std::fs::write(dir.join("reports/redump/2020-01-02T000000Z.txt"), "report").unwrap();
+    assert_eq!(
+        take_snapshot(&dir, Utc.ymd(2020, 1, 2).and_hms(0, 0, 0)).unwrap(),
+        None
+    );
+
+    std::fs::write(dir.join("redump/Redump/Sony - PlayStation.dat"), "psx 2").unwrap();
+    let second = take_snapshot(&dir, Utc.ymd(2020, 1, 3).and_hms(0, 0, 0))
+        .unwrap()
+        .unwrap();
+    update_latest(&dir, &second).unwrap();
+
+    let psx = "redump/Redump/Sony - PlayStation.dat";
+    let saturn = "redump/Redump/Sega - Saturn.dat";
+    assert_eq!(
+        std::fs::read_to_string(first.path.join(psx)).unwrap(),
+        "psx 1"
+    );
+    assert_eq!(
+        std::fs::read_to_string(second.path.join(psx)).unwrap(),
+        "psx 2"
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("latest").join(psx)).unwrap(),
+        "psx 2"
+    );
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let first_saturn = std::fs::metadata(first.path.join(saturn)).unwrap();
+        let second_saturn = std::fs::metadata(second.path.join(saturn)).unwrap();
+        assert_eq!(first_saturn.ino(), second_saturn.ino());
+        let first_psx = std::fs::metadata(first.path.join(psx)).unwrap();
+        let second_psx = std::fs::metadata(second.path.join(psx)).unwrap();
+        assert_ne!(first_psx.ino(), second_psx.ino());
+    }
+}
+
+#[test]
+fn latest_holds_newest_dat_per_system() {
+    let dir = output_dir("latest");
+    let collection = dir.join("no-intro/No-Intro");
+    std::fs::create_dir_all(&collection).unwrap();
+    std::fs::write(collection.join("Nintendo - Game Boy.dat"), "daily").unwrap();
+    std::fs::write(collection.join("Nintendo - Game Boy (2).dat"), "system").unwrap();
+    std::fs::write(collection.join("Nintendo - Virtual Boy (20200101-000000).dat"), "old").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    std::fs::write(collection.join("Nintendo - Virtual Boy (20200202-000000).dat"), "vb").unwrap();
+
+    let snapshot = take_snapshot(&dir, Utc.ymd(2020, 1, 1).and_hms(0, 0, 0))
+        .unwrap()
+        .unwrap();
+    update_latest(&dir, &snapshot).unwrap();
+
+    let latest = dir.join("latest/no-intro/No-Intro");
+    assert_eq!(
+        std::fs::read_to_string(latest.join("Nintendo - Game Boy.dat")).unwrap(),
+        "daily"
+    );
+    assert_eq!(
+        std::fs::read_to_string(latest.join("Nintendo - Game Boy (2).dat")).unwrap(),
+        "system"
+    );
+    assert_eq!(
+        std::fs::read_to_string(latest.join("Nintendo - Virtual Boy.dat")).unwrap(),
+        "vb"
+    );
+    assert_eq!(std::fs::read_dir(&latest).unwrap().count(), 3);
+}
+
+#[test]
+fn retention_prunes_snapshots() {
+    let dir = output_dir("retention");
+    let dates = [
+        (2020, 1, 5),
+        (2020, 1, 20),
+        (2020, 2, 10),
+        (2020, 3, 1),
+        (2020, 3, 15),
+        (2020, 3, 30),
+    ];
+    std::fs::create_dir_all(dir.join("tosec/TOSEC")).unwrap();
+    for (i, (y, m, d)) in dates.iter().enumerate() {
+        std::fs::write(dir.join("tosec/TOSEC/dat.dat"), i.to_string()).unwrap();
+        take_snapshot(&dir, Utc.ymd(*y, *m, *d).and_hms(0, 0, 0))
+            .unwrap()
+            .unwrap();
+    }
+    let now = Utc.ymd(2020, 4, 1).and_hms(0, 0, 0);
+    let names = |dir: &PathBuf| {
+        list_snapshots(dir)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.taken_at.format("%m-%d").to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let retention = Retention {
+        keep_last: Some(2),
+        keep_monthly: Some(3),
+        max_age: None,
+    };
+    prune_snapshots(&dir, &retention, now).unwrap();
+    assert_eq!(names(&dir), vec!["01-20", "02-10", "03-15", "03-30"]);
+
+    let retention = Retention {
+        max_age: Some(Duration::days(30)),
+        ..Retention::default()
+    };
+    let pruned = prune_snapshots(&dir, &retention, now).unwrap();
+    assert_eq!(pruned.len(), 2);
+    assert_eq!(names(&dir), vec!["03-15", "03-30"]);
+
+    // The newest snapshot is kept no matter how old it is.
+    let retention = Retention {
+        keep_last: Some(0),
+        max_age: Some(Duration::days(1)),
+        ..Retention::default()
+    };
+    prune_snapshots(&dir, &retention, now).unwrap();
+    assert_eq!(names(&dir), vec!["03-30"]);
+}
@@ -0,0 +1,51 @@
+//! Checks the helpers shared by every source.
+
+use atsumare::source::attachment_filename;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_DISPOSITION};
+use reqwest::Url;
+
+fn filename(disposition: Option<&str>, url: &str) -> Option<String> {
+    let mut headers = HeaderMap::new();
+    if let Some(disposition) = disposition {
+        headers.insert(
+            CONTENT_DISPOSITION,
+            HeaderValue::from_str(disposition).unwrap(),
+        );
+    }
+    attachment_filename(&headers, &Url::parse(url).unwrap()).ok()
+}
+
+#[test]
+fn attachment_filenames_are_parsed_safely() {
+    let url = "http://localhost/datfile/psx/";
+    assert_eq!(
+        filename(
+            Some(r#"attachment; filename="Sony - PlayStation.zip""#),
+            url
+        )
+        .as_deref(),
+        Some("Sony - PlayStation.zip")
+    );
+    assert_eq!(
+        filename(Some("attachment; filename=psx.zip"), url).as_deref(),
+        Some("psx.zip")
+    );
+    assert_eq!(
+        filename(Some(r#"attachment;FILENAME="psx.zip"; size=1"#), url).as_deref(),
+        Some("psx.zip")
+    );
+    assert_eq!(
+        filename(Some(r#"attachment; filename="../../etc/psx.zip""#), url).as_deref(),
+        Some("psx.zip")
+    );
+    assert_eq!(filename(Some("a"), url).as_deref(), Some("psx"));
+    assert_eq!(
+        filename(Some(r#"attachment; filename="""#), url).as_deref(),
+        Some("psx")
+    );
+    assert_eq!(
+        filename(None, "http://localhost/download/TOSEC.zip").as_deref(),
+        Some("TOSEC.zip")
+    );
+    assert_eq!(filename(None, "http://localhost/"), None);
+}
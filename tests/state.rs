@@ -0,0 +1,18 @@
+//! Checks reading DAT versions for the state manifest.
+
+use atsumare::state::parse_dat_version;
+
+#[test]
+fn dat_version_comes_from_the_header() {
+    let xml = "<datafile>\n\t<header>\n\t\t<name>Sony - PlayStation</name>\n\t\t<version>2020-01-01 00-00-00</version>\n\t</header>\n\t<game name=\"Test\">\n\t\t<version>1.01</version>\n\t</game>\n</datafile>\n";
+    assert_eq!(
+        parse_dat_version(xml).as_deref(),
+        Some("2020-01-01 00-00-00")
+    );
+    let headerless =
+        "<datafile>\n\t<game name=\"Test\">\n\t\t<version>1.01</version>\n\t</game>\n</datafile>\n";
+    assert_eq!(parse_dat_version(headerless), None);
+
+    let cmp = "clrmamepro (\n\tname \"Sega - Saturn\"\n\tversion \"20200101\"\n)\n\ngame (\n\tname \"Test\"\n\tversion 1.01\n)\n";
+    assert_eq!(parse_dat_version(cmp).as_deref(), Some("20200101"));
+}